@@ -26,22 +26,19 @@ pub fn shell_command(command: &Command) -> String {
         .map(|(name, value)| match value {
             Some(value) => format!(
                 "'{name}={value}'",
-                name = name.to_string_lossy().to_string(),
-                value = value.to_string_lossy().to_string()
+                name = name.to_string_lossy(),
+                value = value.to_string_lossy()
             ),
-            None => format!("-u '{name}'", name = name.to_string_lossy().to_string()),
+            None => format!("-u '{name}'", name = name.to_string_lossy()),
         })
         .collect::<Vec<_>>()
         .join(" ");
 
     let shell_command = Iterator::chain(
-        std::iter::once(format!(
-            "'{}'",
-            command.get_program().to_string_lossy().to_string()
-        )),
+        std::iter::once(format!("'{}'", command.get_program().to_string_lossy())),
         command
             .get_args()
-            .map(|arg| format!("'{}'", arg.to_string_lossy().to_string())),
+            .map(|arg| format!("'{}'", arg.to_string_lossy())),
     )
     .collect::<Vec<_>>()
     .join(" ");
@@ -49,13 +46,23 @@ pub fn shell_command(command: &Command) -> String {
     return format!("env {variable_settings} {shell_command}");
 }
 
+// failures of commands talking to a service (e.g. the slurm controller while it restarts), which
+// are likely to go away on their own
+const TRANSIENT_FAILURE_MESSAGES: &[&str] = &[
+    "Socket timed out",
+    "Connection refused",
+    "Connection timed out",
+    "Connection reset by peer",
+    "Resource temporarily unavailable",
+    "Unable to contact slurm controller",
+    "temporarily unable to accept job",
+    "Zero Bytes were transmitted or received",
+];
+
 #[derive(Clone, Debug, thiserror::Error)]
 pub enum CommandError {
     #[error("failed to execute `{command}`\n{io_error}")]
-    Io {
-        command: String,
-        io_error: IoError,
-    },
+    Io { command: String, io_error: IoError },
 
     #[error("failed to execute `{command}`, exit code {code} is non-zero")]
     NonZeroExitCode {
@@ -111,6 +118,21 @@ impl CommandError {
             output: Some(output.into()),
         }
     }
+
+    // only failures whose output is known can be told apart, everything else is permanent
+    pub fn is_transient(&self) -> bool {
+        let output = match self {
+            Self::Io { .. } => return false,
+            Self::NonZeroExitCode { output, .. } => output,
+            Self::SignalTermination { output, .. } => output,
+        };
+
+        output.as_ref().is_some_and(|output| {
+            TRANSIENT_FAILURE_MESSAGES
+                .iter()
+                .any(|message| output.contains(message))
+        })
+    }
 }
 
 pub trait AsCommandError {
@@ -1,16 +1,24 @@
 use camino::Utf8PathBuf as PathBuf;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use miette::{Context, IntoDiagnostic, Result};
-use nix_environment::build_environment;
+use nix_environment::{NixEnvironment, build_environment};
 use serde::Deserialize;
+use std::time::Duration;
 use workflow::{
     generate_specification_string,
     graph::{
         JobGraph,
         execution::{GraphExecutionOptions, execute_job_graph},
+        export::GraphFormat,
+        submission::cancel_submitted_jobs,
+    },
+    job::{
+        execution::{ExecutionMethod, ExecutorSelection},
+        resources::{ResourceLimits, parse_resource_limit},
+        staleness::{ForcedRerun, RerunTrigger},
     },
-    job::execution::ExecutionOption,
     specification::WorkflowSpecification,
+    submission::{read_submission_manifest, write_submission_manifest},
 };
 
 mod commands;
@@ -27,35 +35,341 @@ struct GlobalConfig {
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Execute all jobs which are not up to date
+    Run {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+
+        #[arg(short = 'i', long)]
+        inspect: Option<String>,
+
+        #[arg(short = 'k', long)]
+        keep_going: bool,
+
+        /// Maximum number of jobs running at the same time, defaults to the number of cores
+        #[arg(name = "jobs", short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_parallel_jobs: Option<u32>,
+
+        /// Number of cores available to local jobs, defaults to all cores of the machine
+        #[arg(
+            long,
+            default_value_t = num_cpus::get() as u32,
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        cores: u32,
+
+        /// Amounts of named resources available to local jobs (e.g. `mem_mb=64000`), resources
+        /// which are not given are unlimited
+        #[arg(long, value_parser = parse_resource_limit, num_args = 1..)]
+        resources: Vec<(String, u64)>,
+
+        /// Mark the outputs of all jobs which are not up to date as up to date, without executing
+        #[arg(long)]
+        touch: bool,
+
+        /// Minimum number of seconds between two polls of the state of all slurm jobs
+        #[arg(long, default_value_t = 1)]
+        slurm_poll_interval: u64,
+
+        /// Number of seconds for which slurm may keep failing transiently (e.g. because its
+        /// controller restarts), before jobs are considered failed
+        #[arg(long, default_value_t = 300)]
+        slurm_grace_period: u64,
+    },
+
+    /// Submit all jobs which are not up to date to slurm at once and exit without waiting for
+    /// them, the submitted jobs can be checked on with `status`
+    Submit {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+
+        /// Cancel the jobs of earlier submissions which are still unfinished and submit them again
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print which jobs would be executed and why, without executing anything
+    Plan {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+
+    /// Print the state of every job together with the last recorded run of its outputs
+    Status {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+
+    /// Remove all outputs and logs of the workflow together with their run metadata
+    Clean {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+
+    /// Print the job graph together with the state of every job
+    Graph {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        #[command(flatten)]
+        selection: SelectionArgs,
+
+        #[arg(long, default_value_t)]
+        format: GraphFormat,
+    },
+
+    /// Print the logs of all jobs of a step
+    Logs {
+        #[command(flatten)]
+        graph: GraphArgs,
+
+        step: String,
+    },
+
+    /// Print the workflow specification generated from the workflow flake
+    Specification {
+        #[command(flatten)]
+        workflow: WorkflowArgs,
+    },
+}
+
+#[derive(Args)]
+struct WorkflowArgs {
     #[arg(name = "workflow")]
     workflow_flake_path: PathBuf,
 
     #[arg(short = 'p', long)]
     profile: String,
 
-    #[arg(short = 'e', long)]
-    executor: ExecutionOption,
-
     #[arg(long)]
     force_nix_portable_usage: bool,
+}
+
+#[derive(Args, Default)]
+struct SelectionArgs {
+    /// Targets to build, all targets are built if none are given
+    targets: Vec<String>,
+
+    /// Only build the jobs of the given steps and the jobs they depend on
+    #[arg(long, num_args = 1.., conflicts_with = "only")]
+    until: Vec<String>,
+
+    /// Only build the jobs of the given steps, expecting their inputs to exist already
+    #[arg(long, num_args = 1..)]
+    only: Vec<String>,
+}
+
+#[derive(Args)]
+struct GraphArgs {
+    #[command(flatten)]
+    workflow: WorkflowArgs,
+
+    /// Executor for all steps which do not choose one themselves
+    #[arg(short = 'e', long, default_value_t)]
+    executor: ExecutionMethod,
 
-    #[arg(short = 'i', long)]
-    inspect: Option<String>,
+    /// Use the executor given by `--executor` for all steps, even if they choose one themselves
+    #[arg(long)]
+    override_executor: bool,
+
+    #[arg(long, default_value_t)]
+    rerun_trigger: RerunTrigger,
 
-    #[arg(short = 'k', long)]
-    keep_going: bool,
+    /// Rerun the jobs of the given steps and all jobs depending on them
+    #[arg(long, num_args = 1.., conflicts_with = "forceall")]
+    forcerun: Vec<String>,
 
-    #[arg(name = "jobs", short = 'j', long)]
-    max_parallel_jobs: Option<u32>,
+    /// Rerun all jobs
+    #[arg(long)]
+    forceall: bool,
+}
+impl GraphArgs {
+    fn forced_rerun(&self) -> ForcedRerun {
+        ForcedRerun::new(self.forcerun.clone(), self.forceall)
+    }
 }
 
 fn main() -> Result<()> {
-    // generate workflow steps
     let cli = Cli::parse();
+    match cli.command {
+        CliCommand::Run {
+            graph,
+            selection,
+            inspect,
+            keep_going,
+            max_parallel_jobs,
+            cores,
+            resources,
+            touch,
+            slurm_poll_interval,
+            slurm_grace_period,
+        } => {
+            let job_graph = execute_job_graph(
+                build_job_graph(&graph, &selection)?,
+                GraphExecutionOptions {
+                    max_parallel_jobs: max_parallel_jobs.unwrap_or(cores),
+                    keep_going,
+                    inspection_target: inspect,
+                    rerun_trigger: graph.rerun_trigger,
+                    forced_rerun: graph.forced_rerun(),
+                    touch,
+                    resource_limits: ResourceLimits {
+                        cores,
+                        counters: resources.into_iter().collect(),
+                    },
+                    slurm_poll_interval: Duration::from_secs(slurm_poll_interval),
+                    slurm_grace_period: Duration::from_secs(slurm_grace_period),
+                },
+            )
+            .context("failed to execute job graph")?;
+
+            job_graph.print_report();
+        }
+        CliCommand::Plan { graph, selection } => {
+            build_resolved_job_graph(&graph, &selection)?.print_plan();
+        }
+        CliCommand::Submit {
+            graph,
+            selection,
+            force,
+        } => {
+            let mut job_graph = build_job_graph(&graph, &selection)?;
+            let earlier_manifest = read_submission_manifest()
+                .into_diagnostic()
+                .context("failed to read the submitted jobs")?;
+            let unfinished = match &earlier_manifest {
+                Some(manifest) => job_graph
+                    .reattach_submission(manifest, graph.rerun_trigger)
+                    .into_diagnostic()
+                    .context("failed to reattach to the submitted jobs")?,
+                None => Vec::new(),
+            };
+            // unfinished jobs are not submitted again, unless they are replaced
+            let unfinished = match force {
+                true => {
+                    cancel_submitted_jobs(&unfinished)
+                        .into_diagnostic()
+                        .context("failed to cancel the submitted jobs")?;
+                    Vec::new()
+                }
+                false => unfinished,
+            };
+            let unfinished_count = unfinished.len();
+
+            job_graph
+                .resolve_rerun_reasons(graph.rerun_trigger, &graph.forced_rerun())
+                .into_diagnostic()
+                .context("failed to determine which jobs have to be rerun")?;
+            let manifest = job_graph
+                .submit(unfinished)
+                .into_diagnostic()
+                .context("failed to submit the job graph")?;
+            if manifest.jobs.len() == unfinished_count {
+                println!("all jobs are up to date or still submitted, nothing was submitted");
+            }
+            // finished jobs of the earlier submission are dropped from the manifest
+            if !manifest.jobs.is_empty() || earlier_manifest.is_some() {
+                write_submission_manifest(&manifest)
+                    .into_diagnostic()
+                    .context("failed to record the submitted jobs")?;
+            }
+        }
+        CliCommand::Status { graph, selection } => {
+            let mut job_graph = build_job_graph(&graph, &selection)?;
+            let manifest = read_submission_manifest()
+                .into_diagnostic()
+                .context("failed to read the submitted jobs")?;
+            if let Some(manifest) = manifest {
+                job_graph
+                    .reattach_submission(&manifest, graph.rerun_trigger)
+                    .into_diagnostic()
+                    .context("failed to reattach to the submitted jobs")?;
+            }
+
+            job_graph
+                .resolve_rerun_reasons(graph.rerun_trigger, &graph.forced_rerun())
+                .into_diagnostic()
+                .context("failed to determine which jobs have to be rerun")?;
+            job_graph
+                .print_status()
+                .into_diagnostic()
+                .context("failed to determine the job status")?;
+        }
+        CliCommand::Clean { graph, selection } => {
+            build_job_graph(&graph, &selection)?
+                .clean()
+                .into_diagnostic()
+                .context("failed to clean the workflow")?;
+        }
+        CliCommand::Graph {
+            graph,
+            selection,
+            format,
+        } => {
+            println!(
+                "{}",
+                build_resolved_job_graph(&graph, &selection)?.export(format)
+            );
+        }
+        CliCommand::Logs { graph, step } => {
+            let job_graph = build_job_graph(&graph, &SelectionArgs::default())?;
+            let mut logs = job_graph
+                .jobs()
+                .map(|job| job.step())
+                .filter(|job_step| job_step.name == step)
+                .map(|job_step| job_step.log.clone())
+                .collect::<Vec<_>>();
+            logs.sort();
+            logs.dedup();
+
+            if logs.is_empty() {
+                miette::bail!("the workflow does not contain a step named `{step}`");
+            }
+            for log in logs {
+                let contents = std::fs::read_to_string(&log)
+                    .into_diagnostic()
+                    .context(format!("failed to read the log `{log}`"))?;
+                println!("==> {log} <==\n{contents}");
+            }
+        }
+        CliCommand::Specification { workflow } => {
+            let (_, specification_string) = generate_specification(&workflow)?;
+            // only print specifications we are able to parse
+            WorkflowSpecification::parse(&specification_string)
+                .context("failed to generate workflow specification")?;
+            println!("{specification_string}");
+        }
+    }
+
+    Ok(())
+}
+
+fn generate_specification(args: &WorkflowArgs) -> Result<(Box<dyn NixEnvironment>, String)> {
     let config: GlobalConfig = serde_yaml::from_str(
         &std::fs::read_to_string(format!(
             "{workflow}/config.yaml",
-            workflow = cli.workflow_flake_path
+            workflow = args.workflow_flake_path
         ))
         .into_diagnostic()
         .context("failed to read configuration")?,
@@ -66,40 +380,72 @@ fn main() -> Result<()> {
     let nix_environment = build_environment(
         config.nix_local_cache_directory_path,
         config.nix_distributed_cache_path,
-        cli.force_nix_portable_usage,
+        args.force_nix_portable_usage,
     )
     .into_diagnostic()
     .context("failed to build nix environment")?;
 
     let specification_string =
-        &generate_specification_string(&nix_environment, &cli.workflow_flake_path, &cli.profile)
+        generate_specification_string(&nix_environment, &args.workflow_flake_path, &args.profile)
             .into_diagnostic()
             .context(format!(
                 "failed to generate workflow specification from `{workflow_flake}`",
-                workflow_flake = cli.workflow_flake_path
+                workflow_flake = args.workflow_flake_path
             ))?;
 
-    let workflow_specification = WorkflowSpecification::parse(specification_string)
+    Ok((nix_environment, specification_string))
+}
+
+fn build_job_graph(args: &GraphArgs, selection: &SelectionArgs) -> Result<JobGraph> {
+    let (nix_environment, specification_string) = generate_specification(&args.workflow)?;
+
+    let mut workflow_specification = WorkflowSpecification::parse(specification_string)
         .context("failed to generate workflow specification")?;
+    workflow_specification
+        .retain_targets(&selection.targets)
+        .into_diagnostic()
+        .context("failed to select targets")?;
 
-    let job_graph = JobGraph::new(
+    let mut job_graph = JobGraph::new(
         workflow_specification,
         &nix_environment,
-        &cli.workflow_flake_path,
-        &cli.profile,
-    );
-
-    let job_graph = execute_job_graph(
-        job_graph,
-        GraphExecutionOptions {
-            max_parallel_jobs: cli.max_parallel_jobs.unwrap_or(1),
-            keep_going: cli.keep_going,
-            inspection_target: cli.inspect,
+        &args.workflow.workflow_flake_path,
+        &args.workflow.profile,
+        ExecutorSelection {
+            default: args.executor,
+            overriding: args.override_executor,
         },
     )
-    .context("failed to execute job graph")?;
+    .into_diagnostic()
+    .context("failed to build job graph")?;
 
-    job_graph.print_report();
+    if !selection.until.is_empty() {
+        job_graph
+            .retain_until(&selection.until)
+            .into_diagnostic()
+            .context("failed to select steps")?;
+    }
+    if !selection.only.is_empty() {
+        job_graph
+            .retain_only(&selection.only)
+            .into_diagnostic()
+            .context("failed to select steps")?;
+    }
 
-    Ok(())
+    job_graph
+        .ensure_steps_exist(&args.forcerun)
+        .into_diagnostic()
+        .context("failed to select steps to rerun")?;
+
+    Ok(job_graph)
+}
+
+fn build_resolved_job_graph(args: &GraphArgs, selection: &SelectionArgs) -> Result<JobGraph> {
+    let mut job_graph = build_job_graph(args, selection)?;
+    job_graph
+        .resolve_rerun_reasons(args.rerun_trigger, &args.forced_rerun())
+        .into_diagnostic()
+        .context("failed to determine which jobs have to be rerun")?;
+
+    Ok(job_graph)
 }
@@ -12,8 +12,8 @@ mod portable_distributed;
 pub enum Error {
     #[error("nix could neither be executed with `{nix_check_command:?}` nor with `{nix_portable_check_command:?}`")]
     NixUnavailable {
-        nix_check_command: Command,
-        nix_portable_check_command: Command,
+        nix_check_command: Box<Command>,
+        nix_portable_check_command: Box<Command>,
     },
 }
 
@@ -64,7 +64,12 @@ impl std::fmt::Display for FlakeSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FlakeSource::_Name(name) => write!(f, "{}", name),
-            FlakeSource::Path(path) => write!(f, "./{}", path),
+            // the flake is referred to by its absolute path, since commands may run in another
+            // directory than the one they were built in (e.g. in scratch space)
+            FlakeSource::Path(path) => match camino::absolute_utf8(path) {
+                Ok(path) => write!(f, "{}", path),
+                Err(_) => write!(f, "./{}", path),
+            },
         }
     }
 }
@@ -73,6 +78,7 @@ pub struct FlakeOutput {
     name: Option<String>,
 }
 impl FlakeOutput {
+    #[allow(unused)]
     pub fn new_default(source: FlakeSource) -> Self {
         Self { source, name: None }
     }
@@ -99,6 +105,11 @@ pub fn build_environment(
     cache_distributed: PathBuf,
     force_nix_portable_usage: bool,
 ) -> Result<Box<dyn NixEnvironment>, Error> {
+    // relative cache paths would refer to another location for commands which do not run in
+    // the current directory
+    let cache_local = camino::absolute_utf8(&cache_local).unwrap_or(cache_local);
+    let cache_distributed = camino::absolute_utf8(&cache_distributed).unwrap_or(cache_distributed);
+
     let mut nix_check_command = nix_version_command(None);
     let mut nix_portable_check_command = nix_version_command(Some(PortableOptions::new(
         cache_local.parent().expect("").to_owned(),
@@ -120,8 +131,8 @@ pub fn build_environment(
         }))
     } else {
         Err(Error::NixUnavailable {
-            nix_check_command,
-            nix_portable_check_command,
+            nix_check_command: Box::new(nix_check_command),
+            nix_portable_check_command: Box::new(nix_portable_check_command),
         })
     }
 }
@@ -62,7 +62,7 @@ impl NixRunCommand for NixPortableDistributedRunCommand {
                 "{unpack_cache} && {run} && {distribute_cache}",
                 unpack_cache = shell_command(&self.unpack_cache),
                 run = shell_command(&self.run),
-                distribute_cache = shell_command(&distribute_cache)
+                distribute_cache = shell_command(distribute_cache)
             )
         } else {
             format!(
@@ -16,6 +16,7 @@ impl From<std::io::Error> for IoError {
     }
 }
 
+#[allow(unused)]
 pub trait LockOrPanic<T: ?Sized> {
     fn lock_or_panic(&self) -> MutexGuard<'_, T>;
 }
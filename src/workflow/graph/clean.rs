@@ -0,0 +1,54 @@
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+
+use crate::{
+    utils::IoError,
+    workflow::metadata::{MetadataError, remove_output_metadata},
+};
+
+use super::JobGraph;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CleanError {
+    #[error("failed to remove `{0}`\n{1}")]
+    Removal(PathBuf, IoError),
+
+    #[error("failed to remove the run metadata\n{0}")]
+    Metadata(
+        #[source]
+        #[from]
+        MetadataError,
+    ),
+}
+
+impl JobGraph {
+    // removes every output and log together with the associated run metadata
+    pub fn clean(&self) -> Result<(), CleanError> {
+        for job in self.jobs() {
+            let step = job.step();
+            for output in step.outputs.iter() {
+                remove_path(output)?;
+                remove_output_metadata(output)?;
+            }
+            remove_path(&step.log)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn remove_path(path: &Path) -> Result<(), CleanError> {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+
+    match result {
+        Ok(()) => {
+            println!("removed `{path}`");
+            Ok(())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(CleanError::Removal(path.to_owned(), err.into())),
+    }
+}
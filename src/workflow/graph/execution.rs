@@ -1,21 +1,65 @@
 use indicatif::MultiProgress;
-use petgraph::graph::NodeIndex;
+use miette::{Context, IntoDiagnostic};
+use petgraph::{Direction, algo::toposort, graph::NodeIndex};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    os::fd::AsRawFd,
+    time::{Duration, Instant},
+};
 
-use crate::workflow::job::{AsFailedJob, JobError, FailedJob, Job};
+use crate::workflow::{
+    interrupt::{install_interrupt_handler, interrupted},
+    job::{
+        FailedJob, IntoFailedJob, Job, JobError, JobExecutionSettings, RunningJob,
+        execution::{
+            ExecutionMethod, ExitNotification, cancel_slurm_jobs, set_slurm_grace_period,
+            set_slurm_poll_interval, shares_slurm_allocation, submit_job_group,
+        },
+        resources::{ResourceGrant, ResourceLimits, ResourcePool},
+        staleness::{ForcedRerun, RerunReason, RerunTrigger},
+    },
+};
 
-use super::{JobGraph, MaybeTransitioning, progress::build_progress_style};
+use super::{JobGraph, MaybeTransitioning, priority::JobRank, progress::build_progress_style};
+
+const PROGRESS_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct GraphExecutionState {
     job_execution_index: u32,
+    job_execution_count: u32,
     progress: MultiProgress,
     failure: bool,
+    // pending jobs whose parents are all finished
+    ready: VecDeque<NodeIndex>,
+    running: Vec<NodeIndex>,
+    next_polls: HashMap<NodeIndex, Instant>,
+    next_progress_refresh: Instant,
+    resources: ResourcePool,
+    grants: HashMap<NodeIndex, ResourceGrant>,
+    ranks: HashMap<NodeIndex, JobRank>,
+    // jobs which were already submitted as part of a group allocation
+    grouped: HashSet<NodeIndex>,
 }
 impl GraphExecutionState {
-    fn new() -> Self {
+    fn new(
+        job_execution_count: u32,
+        resource_limits: ResourceLimits,
+        ranks: HashMap<NodeIndex, JobRank>,
+    ) -> Self {
         Self {
             job_execution_index: 1,
+            job_execution_count,
             progress: MultiProgress::new(),
             failure: false,
+            ready: VecDeque::new(),
+            running: Vec::new(),
+            next_polls: HashMap::new(),
+            next_progress_refresh: Instant::now(),
+            resources: ResourcePool::new(resource_limits),
+            grants: HashMap::new(),
+            ranks,
+            grouped: HashSet::new(),
         }
     }
 }
@@ -24,29 +68,64 @@ pub struct GraphExecutionOptions {
     pub max_parallel_jobs: u32,
     pub keep_going: bool,
     pub inspection_target: Option<String>,
+    pub rerun_trigger: RerunTrigger,
+    pub forced_rerun: ForcedRerun,
+    pub touch: bool,
+    pub resource_limits: ResourceLimits,
+    pub slurm_poll_interval: Duration,
+    pub slurm_grace_period: Duration,
 }
 
 pub fn execute_job_graph(
     mut graph: JobGraph,
     options: GraphExecutionOptions,
 ) -> miette::Result<JobGraph> {
-    let mut state = GraphExecutionState::new();
-    while !graph.is_finished() {
-        for job_index in graph.job_indices().collect::<Vec<_>>() {
-            let job: Job =
-                std::mem::replace(graph.job_mut(job_index), MaybeTransitioning::Transitioning)
-                    .expect(
-                        "transitioning job was previously stable or got replaced \
-                        with a stable job in previous iteration after transition",
-                    );
-            let job = match update_job(&graph, job_index, job, &mut state, &options) {
-                Ok(job) => job,
-                Err(failed) => {
-                    state.failure = !options.keep_going;
-                    Job::Failed(failed)
-                }
-            };
-            let _ = std::mem::replace(graph.job_mut(job_index), job.into());
+    set_slurm_poll_interval(options.slurm_poll_interval);
+    set_slurm_grace_period(options.slurm_grace_period);
+
+    graph
+        .resolve_rerun_reasons(options.rerun_trigger, &options.forced_rerun)
+        .into_diagnostic()
+        .context("failed to determine which jobs have to be rerun")?;
+
+    let ranks = graph
+        .job_ranks()
+        .into_diagnostic()
+        .context("failed to determine the order of execution")?;
+    let mut state = GraphExecutionState::new(
+        graph.rerun_job_count(),
+        options.resource_limits.clone(),
+        ranks,
+    );
+    state.ready.extend(
+        graph
+            .job_indices()
+            .filter(|job_index| graph.parents(*job_index).next().is_none()),
+    );
+
+    install_interrupt_handler();
+    loop {
+        if interrupted() && !state.failure {
+            state.failure = true;
+            let _ = state.progress.println("interrupted, terminating all jobs");
+        }
+
+        start_ready_jobs(&mut graph, &mut state, &options);
+        if state.failure {
+            terminate_unfinished_jobs(&mut graph, &mut state);
+            break;
+        }
+        if state.running.is_empty() {
+            break;
+        }
+
+        for job_index in wait_for_running_jobs(&graph, &mut state) {
+            check_running_job(&mut graph, job_index, &mut state, &options);
+        }
+
+        if Instant::now() >= state.next_progress_refresh {
+            refresh_progress(&mut graph, &mut state, &options);
+            state.next_progress_refresh = Instant::now() + PROGRESS_REFRESH_INTERVAL;
         }
     }
 
@@ -55,56 +134,470 @@ pub fn execute_job_graph(
     return Ok(graph);
 }
 
-pub fn update_job(
-    graph: &JobGraph,
+// replaces the job with the result of the transition, parents of the job stay accessible through
+// the graph during the transition
+fn transition_job(
+    graph: &mut JobGraph,
     job_index: NodeIndex,
-    job: Job,
+    transition: impl FnOnce(&JobGraph, Job) -> Job,
+) {
+    let job: Job = std::mem::replace(graph.job_mut(job_index), MaybeTransitioning::Transitioning)
+        .expect("only one job is transitioning at a time");
+    let job = transition(graph, job);
+    let _ = std::mem::replace(graph.job_mut(job_index), job.into());
+}
+
+fn fail_on_error(
+    result: Result<Job, FailedJob>,
     state: &mut GraphExecutionState,
     options: &GraphExecutionOptions,
-) -> Result<Job, FailedJob> {
-    match job {
-        Job::Pending(pending) if state.failure => Ok(pending.terminate().into()),
-        Job::Pending(pending)
-            if graph.parents(job_index).all(|p| p.successful())
-                && graph.count_stable(|job| job.is_running()) < options.max_parallel_jobs =>
-        {
-            let progress_style = build_progress_style(state.job_execution_index, graph.job_count());
-            state.job_execution_index += 1;
-
-            let inspect = options
-                .inspection_target
-                .as_ref()
-                .is_some_and(|name| *name == pending.step.name);
-            pending
-                .execute(&state.progress, progress_style, options.keep_going, inspect)
-                .map(|job| job.into())
+) -> Job {
+    result.unwrap_or_else(|failed| {
+        state.failure |= !options.keep_going;
+        Job::Failed(failed)
+    })
+}
+
+// children become ready as soon as all of their parents finished, regardless of whether they
+// finished successfully
+fn on_job_finished(graph: &JobGraph, job_index: NodeIndex, state: &mut GraphExecutionState) {
+    if let Some(grant) = state.grants.remove(&job_index) {
+        state.resources.release(&grant);
+    }
+
+    for child_index in graph.child_indices(job_index) {
+        if graph.parents(child_index).all(|parent| parent.finished()) {
+            state.ready.push_back(child_index);
         }
-        job @ Job::Pending(_)
-            if graph.parents(job_index).all(|p| p.finished())
-                && graph.parents(job_index).any(|p| p.failed()) =>
+    }
+}
+
+fn start_ready_jobs(
+    graph: &mut JobGraph,
+    state: &mut GraphExecutionState,
+    options: &GraphExecutionOptions,
+) {
+    // jobs which do not have to be executed finish immediately and possibly make their children
+    // ready, so we continue until nothing changes anymore
+    let mut changed = true;
+    while changed && !state.failure {
+        changed = false;
+        let mut ready = Vec::from(std::mem::take(&mut state.ready));
+        ready.sort_by_key(|job_index| Reverse(state.ranks[job_index]));
+        for job_index in ready {
+            if let Err(err) = recheck_rerun_parent(graph, job_index, state, options) {
+                transition_job(graph, job_index, |_, job| {
+                    fail_on_error(Err(err.into_failed_job(job.report(), None)), state, options)
+                });
+                on_job_finished(graph, job_index, state);
+                changed = true;
+                continue;
+            }
+
+            let needs_slot = graph.job(job_index).rerun_reason().is_some() && !options.touch;
+            let slot_available = (state.running.len() as u32) < options.max_parallel_jobs;
+            if state.failure || (needs_slot && !slot_available) {
+                state.ready.push_back(job_index);
+                continue;
+            }
+
+            let grant = match needs_slot {
+                true => acquire_resources(graph.job(job_index), &mut state.resources),
+                false => Some(ResourceGrant::unaccounted(
+                    &graph.job(job_index).step().resources,
+                )),
+            };
+            let Some(grant) = grant else {
+                state.ready.push_back(job_index);
+                continue;
+            };
+            // the grant is kept until the job finished, which is immediately for most failures
+            state.grants.insert(job_index, grant.clone());
+
+            if needs_slot {
+                submit_group(graph, job_index, state);
+            }
+            transition_job(graph, job_index, |graph, job| {
+                let result = start_job(graph, job_index, job, &grant, state, options);
+                fail_on_error(result, state, options)
+            });
+            changed = true;
+
+            if graph.job(job_index).is_running() {
+                state.running.push(job_index);
+            } else {
+                on_job_finished(graph, job_index, state);
+            }
+        }
+    }
+
+    submit_started_jobs(graph);
+}
+
+// with checksums as rerun trigger, a job is only rerun because of its parents if their outputs
+// actually changed, which is only known once they finished. jobs which are already part of a
+// slurm allocation are run by it anyway
+fn recheck_rerun_parent(
+    graph: &mut JobGraph,
+    job_index: NodeIndex,
+    state: &mut GraphExecutionState,
+    options: &GraphExecutionOptions,
+) -> Result<(), JobError> {
+    if options.rerun_trigger != RerunTrigger::Checksum
+        || options.touch
+        || state.grouped.contains(&job_index)
+        || graph.parents(job_index).any(|parent| parent.failed())
+    {
+        return Ok(());
+    }
+
+    let Job::Pending(pending) = graph.job_mut_stable(job_index) else {
+        unreachable!("only pending jobs are ready")
+    };
+    if !matches!(pending.rerun_reason(), Some(RerunReason::RerunParent(_))) {
+        return Ok(());
+    }
+
+    let reason = pending.determine_rerun_reason(RerunTrigger::Checksum)?;
+    if reason.is_none() {
+        state.job_execution_count -= 1;
+    }
+    pending.set_rerun_reason(reason);
+
+    Ok(())
+}
+
+// jobs are only submitted once all ready jobs were started, so that slurm jobs with the same
+// options can be submitted together as a job array
+fn submit_started_jobs(graph: &mut JobGraph) {
+    let jobs = graph
+        .0
+        .node_weights_mut()
+        .filter_map(|job| match job {
+            MaybeTransitioning::Stable(Job::Running(running)) => Some(running.as_mut()),
+            _ => None,
+        })
+        .collect();
+
+    RunningJob::submit_all(jobs);
+}
+
+// slurm jobs of the same group which only depend on the started job, on each other or on
+// successfully finished jobs are submitted together with the started job as a single allocation,
+// as long as they can run on the same nodes as the started job
+fn submit_group(graph: &mut JobGraph, job_index: NodeIndex, state: &mut GraphExecutionState) {
+    let groupable = |job: &Job, group: &str| match job {
+        Job::Pending(pending) => {
+            pending.step.group.as_deref() == Some(group)
+                && pending.rerun_reason().is_some()
+                && pending.execution_method() == ExecutionMethod::Slurm
+        }
+        _ => false,
+    };
+
+    let Some(group) = graph.job(job_index).step().group.clone() else {
+        return;
+    };
+    if state.grouped.contains(&job_index)
+        || !groupable(graph.job(job_index), &group)
+        || graph.parents(job_index).any(|parent| parent.failed())
+    {
+        return;
+    }
+
+    let command = |job_index| match graph.job(job_index) {
+        Job::Pending(pending) => pending.command(),
+        _ => unreachable!("only pending jobs are grouped"),
+    };
+    let mut members = vec![job_index];
+    for candidate in
+        toposort(&graph.0, None).expect("job graph was constructed as an acyclic graph")
+    {
+        if members.contains(&candidate)
+            || state.grouped.contains(&candidate)
+            || !groupable(graph.job(candidate), &group)
+            || !shares_slurm_allocation(command(job_index), command(candidate))
         {
+            continue;
+        }
+
+        let parents = graph
+            .0
+            .neighbors_directed(candidate, Direction::Incoming)
+            .collect::<Vec<_>>();
+        let connected = parents.iter().any(|parent| members.contains(parent));
+        let satisfied = parents.iter().all(|parent| {
+            members.contains(parent) || matches!(graph.job(*parent), Job::Successful(_))
+        });
+        if connected && satisfied {
+            members.push(candidate);
+        }
+    }
+    if members.len() == 1 {
+        return;
+    }
+
+    let member_commands = members
+        .iter()
+        .map(|member| {
+            let Job::Pending(pending) = graph.job(*member) else {
+                unreachable!("only pending jobs are grouped")
+            };
             let parents = graph
-                .parents(job_index)
-                .filter(|parent| parent.failed())
-                .map(|parent| parent.step().clone())
+                .0
+                .neighbors_directed(*member, Direction::Incoming)
+                .filter_map(|parent| members.iter().position(|member| *member == parent))
                 .collect();
+            (pending.command(), parents)
+        })
+        .collect::<Vec<_>>();
+    let grants = members
+        .iter()
+        .map(|member| ResourceGrant::unaccounted(&graph.job(*member).step().resources))
+        .collect::<Vec<_>>();
+    let commands = submit_job_group(&member_commands, &grants);
 
-            Err(JobError::ParentsFailed { parents }.as_failed_job(job.report(), None))
-        }
-        job @ Job::Pending(_) => Ok(job),
+    for (member, command) in members.into_iter().zip(commands) {
+        let Job::Pending(pending) = graph.job_mut_stable(member) else {
+            unreachable!("only pending jobs are grouped")
+        };
+        pending.replace_command(command);
+        state.grouped.insert(member);
+    }
+}
 
-        Job::Running(running) if state.failure => running.terminate().map(|job| job.into()),
-        Job::Running(mut running) => {
-            if running.done()? {
-                let finished_job = running.finish()?;
-                Ok(finished_job.into())
-            } else {
-                Ok(Job::Running(running.update_progress()?))
+// jobs whose resources can never be satisfied get a grant anyway, so that they fail right away
+fn acquire_resources(job: &Job, pool: &mut ResourcePool) -> Option<ResourceGrant> {
+    let Job::Pending(pending) = job else {
+        unreachable!("only pending jobs are ready")
+    };
+
+    let resources = &pending.step.resources;
+    if pending.execution_method() != ExecutionMethod::Default
+        || pool.ensure_satisfiable(resources).is_err()
+    {
+        return Some(ResourceGrant::unaccounted(resources));
+    }
+
+    pool.try_acquire(resources)
+}
+
+fn start_job(
+    graph: &JobGraph,
+    job_index: NodeIndex,
+    job: Job,
+    grant: &ResourceGrant,
+    state: &mut GraphExecutionState,
+    options: &GraphExecutionOptions,
+) -> Result<Job, FailedJob> {
+    if graph.parents(job_index).any(|parent| parent.failed()) {
+        let parents = graph
+            .parents(job_index)
+            .filter(|parent| parent.failed())
+            .map(|parent| parent.step().clone())
+            .collect();
+        return Err(JobError::ParentsFailed { parents }.into_failed_job(job.report(), None));
+    }
+
+    if let Job::Pending(pending) = &job
+        && pending.execution_method() == ExecutionMethod::Default
+        && let Err(err) = state.resources.ensure_satisfiable(&pending.step.resources)
+    {
+        return Err(err.into_failed_job(job.report(), None));
+    }
+
+    let Job::Pending(pending) = job else {
+        unreachable!("only pending jobs are ready")
+    };
+
+    let progress_style = build_progress_style(state.job_execution_index, state.job_execution_count);
+    if pending.rerun_reason().is_some() && !options.touch {
+        state.job_execution_index += 1;
+    }
+
+    let inspect = options
+        .inspection_target
+        .as_ref()
+        .is_some_and(|name| *name == pending.step.name);
+    pending
+        .execute(
+            &state.progress,
+            JobExecutionSettings {
+                progress_style,
+                prefer_warnings: options.keep_going,
+                inspect,
+                rerun_trigger: options.rerun_trigger,
+                touch: options.touch,
+            },
+            grant,
+        )
+        .map(|job| job.into())
+}
+
+// blocks until a local job exits, a job has to be polled or the progress has to be refreshed,
+// returns the jobs which might have finished
+fn wait_for_running_jobs(graph: &JobGraph, state: &mut GraphExecutionState) -> Vec<NodeIndex> {
+    let now = Instant::now();
+    let mut deadline = state.next_progress_refresh;
+    let mut poll_fds = Vec::new();
+    let mut poll_fd_jobs = Vec::new();
+    let mut polled_jobs = Vec::new();
+    for job_index in state.running.iter().copied() {
+        let Job::Running(running) = graph.job(job_index) else {
+            unreachable!("only running jobs are tracked as running")
+        };
+
+        match running.exit_notification() {
+            ExitNotification::Readable(fd) => {
+                poll_fds.push(libc::pollfd {
+                    fd: fd.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                });
+                poll_fd_jobs.push(job_index);
+            }
+            ExitNotification::Polling(interval) => {
+                let next_poll = *state.next_polls.entry(job_index).or_insert(now);
+                if next_poll <= now {
+                    state.next_polls.insert(job_index, now + interval);
+                    polled_jobs.push(job_index);
+                }
+                deadline = deadline.min(state.next_polls[&job_index]);
             }
         }
+    }
+
+    // polling jobs that are due is handled right away, without blocking
+    let timeout = if polled_jobs.is_empty() {
+        deadline.saturating_duration_since(now)
+    } else {
+        Duration::ZERO
+    };
+    let ready_count = unsafe {
+        libc::poll(
+            poll_fds.as_mut_ptr(),
+            poll_fds.len() as libc::nfds_t,
+            timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX),
+        )
+    };
+
+    // a failed poll (e.g. interrupted by a signal) only delays noticing exits, so all jobs
+    // waited on through file descriptors are checked in this case
+    if ready_count < 0 {
+        polled_jobs.extend(poll_fd_jobs);
+        return polled_jobs;
+    }
+
+    polled_jobs.extend(
+        poll_fds
+            .iter()
+            .zip(poll_fd_jobs)
+            .filter(|(poll_fd, _)| poll_fd.revents != 0)
+            .map(|(_, job_index)| job_index),
+    );
+    polled_jobs
+}
 
-        job @ Job::Successful(_) => Ok(job),
-        job @ Job::Failed(_) => Ok(job),
-        job @ Job::Terminated(_) => Ok(job),
+fn check_running_job(
+    graph: &mut JobGraph,
+    job_index: NodeIndex,
+    state: &mut GraphExecutionState,
+    options: &GraphExecutionOptions,
+) {
+    transition_job(graph, job_index, |_, job| {
+        let Job::Running(mut running) = job else {
+            unreachable!("only running jobs are tracked as running")
+        };
+
+        let result = match running.done() {
+            Ok(true) => running.finish().map(|job| job.into()),
+            Ok(false) => Ok(Job::Running(running)),
+            Err(failed) => Err(failed),
+        };
+        fail_on_error(result, state, options)
+    });
+
+    if !graph.job(job_index).is_running() {
+        state
+            .running
+            .retain(|running_index| *running_index != job_index);
+        state.next_polls.remove(&job_index);
+        on_job_finished(graph, job_index, state);
     }
 }
+
+fn refresh_progress(
+    graph: &mut JobGraph,
+    state: &mut GraphExecutionState,
+    options: &GraphExecutionOptions,
+) {
+    for job_index in state.running.clone() {
+        transition_job(graph, job_index, |_, job| {
+            let Job::Running(running) = job else {
+                unreachable!("only running jobs are tracked as running")
+            };
+
+            let result = running.update_progress().map(Job::from);
+            fail_on_error(result, state, options)
+        });
+
+        if !graph.job(job_index).is_running() {
+            state
+                .running
+                .retain(|running_index| *running_index != job_index);
+            state.next_polls.remove(&job_index);
+            on_job_finished(graph, job_index, state);
+        }
+    }
+}
+
+fn terminate_unfinished_jobs(graph: &mut JobGraph, state: &mut GraphExecutionState) {
+    // grouped jobs which did not start yet have no child which would cancel their allocation
+    let allocations = graph
+        .jobs()
+        .filter_map(|job| match job {
+            Job::Pending(pending) => pending.command().slurm_allocation(),
+            _ => None,
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    if !allocations.is_empty()
+        && let Err(err) = cancel_slurm_jobs(&allocations)
+    {
+        let _ = state.progress.println(format!(
+            "warning: failed to cancel the slurm group allocations: {err}"
+        ));
+    }
+
+    for job_index in graph.job_indices().collect::<Vec<_>>() {
+        transition_job(graph, job_index, |_, job| match job {
+            Job::Pending(pending) => pending.terminate().into(),
+            job => job,
+        });
+    }
+
+    // running jobs are terminated all at once, since cancelling them one by one can take a
+    // while for executors like slurm
+    let running_jobs = state
+        .running
+        .iter()
+        .map(|job_index| {
+            match std::mem::replace(graph.job_mut(*job_index), MaybeTransitioning::Transitioning) {
+                MaybeTransitioning::Stable(Job::Running(running)) => *running,
+                _ => unreachable!("only running jobs are tracked as running"),
+            }
+        })
+        .collect();
+    let terminated_jobs = RunningJob::terminate_all(running_jobs);
+    for (job_index, terminated) in state.running.iter().zip(terminated_jobs) {
+        let job: Job = match terminated {
+            Ok(terminated) => terminated.into(),
+            Err(failed) => failed.into(),
+        };
+        let _ = std::mem::replace(graph.job_mut(*job_index), job.into());
+    }
+
+    state.running.clear();
+    state.ready.clear();
+}
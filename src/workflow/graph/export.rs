@@ -0,0 +1,171 @@
+use camino::Utf8PathBuf as PathBuf;
+use clap::ValueEnum;
+use derive_more::Display;
+use petgraph::visit::EdgeRef;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::workflow::job::{Job, execution::ExecutionMethod};
+
+use super::JobGraph;
+
+#[derive(Display, Default, Clone, Copy, Debug, ValueEnum)]
+pub enum GraphFormat {
+    #[default]
+    #[display("dot")]
+    Dot,
+    #[display("mermaid")]
+    Mermaid,
+    #[display("json")]
+    Json,
+}
+
+#[derive(Display, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ExportedJobState {
+    #[display("up to date")]
+    UpToDate,
+    #[display("will run")]
+    WillRun,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedJob<'g> {
+    id: usize,
+    step: &'g str,
+    outputs: &'g [PathBuf],
+    executor: ExecutionMethod,
+    state: ExportedJobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rerun_reason: Option<String>,
+}
+impl ExportedJob<'_> {
+    fn label_lines(&self) -> Vec<String> {
+        let mut lines = vec![self.step.to_owned()];
+        lines.extend(self.outputs.iter().map(|output| output.to_string()));
+        lines.push(format!("executor: {executor}", executor = self.executor));
+        lines.push(self.state.to_string());
+        lines
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedDependency<'g> {
+    parent: usize,
+    child: usize,
+    inputs: &'g BTreeSet<String>,
+}
+impl ExportedDependency<'_> {
+    fn label(&self) -> String {
+        self.inputs.iter().cloned().collect::<Vec<_>>().join(", ")
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedGraph<'g> {
+    jobs: Vec<ExportedJob<'g>>,
+    dependencies: Vec<ExportedDependency<'g>>,
+}
+
+impl JobGraph {
+    // expects rerun reasons to be resolved
+    pub fn export(&self, format: GraphFormat) -> String {
+        let graph = self.exported();
+        match format {
+            GraphFormat::Dot => dot(&graph),
+            GraphFormat::Mermaid => mermaid(&graph),
+            GraphFormat::Json => serde_json::to_string_pretty(&graph)
+                .expect("expected serialization of the job graph to always succeed"),
+        }
+    }
+
+    fn exported(&self) -> ExportedGraph<'_> {
+        let jobs = self
+            .job_indices()
+            .map(|job_index| {
+                let Job::Pending(pending) = self.job(job_index) else {
+                    unreachable!("only called before execution")
+                };
+
+                ExportedJob {
+                    id: job_index.index(),
+                    step: &pending.step.name,
+                    outputs: &pending.step.outputs,
+                    executor: pending.execution_method(),
+                    state: match pending.rerun_reason() {
+                        Some(_) => ExportedJobState::WillRun,
+                        None => ExportedJobState::UpToDate,
+                    },
+                    rerun_reason: pending.rerun_reason().map(|reason| reason.to_string()),
+                }
+            })
+            .collect();
+
+        let dependencies = self
+            .0
+            .edge_references()
+            .map(|edge| ExportedDependency {
+                parent: edge.source().index(),
+                child: edge.target().index(),
+                inputs: &edge.weight().inputs,
+            })
+            .collect();
+
+        ExportedGraph { jobs, dependencies }
+    }
+}
+
+fn dot(graph: &ExportedGraph) -> String {
+    fn escape(text: &str) -> String {
+        text.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
+    let mut dot = String::from("digraph workflow {\n    node [shape=box];\n");
+    for job in graph.jobs.iter() {
+        let label = job
+            .label_lines()
+            .iter()
+            .map(|line| escape(line))
+            .collect::<Vec<_>>()
+            .join("\\n");
+        dot += &format!("    {id} [label=\"{label}\"];\n", id = job.id);
+    }
+    for dependency in graph.dependencies.iter() {
+        dot += &format!(
+            "    {parent} -> {child} [label=\"{label}\"];\n",
+            parent = dependency.parent,
+            child = dependency.child,
+            label = escape(&dependency.label()),
+        );
+    }
+    dot += "}";
+
+    dot
+}
+
+fn mermaid(graph: &ExportedGraph) -> String {
+    fn escape(text: &str) -> String {
+        text.replace('"', "#quot;")
+    }
+
+    let mut mermaid = String::from("flowchart TD\n");
+    for job in graph.jobs.iter() {
+        let label = job
+            .label_lines()
+            .iter()
+            .map(|line| escape(line))
+            .collect::<Vec<_>>()
+            .join("<br>");
+        mermaid += &format!("    job{id}[\"{label}\"]\n", id = job.id);
+    }
+    for dependency in graph.dependencies.iter() {
+        mermaid += &format!(
+            "    job{parent} -->|\"{label}\"| job{child}\n",
+            parent = dependency.parent,
+            child = dependency.child,
+            label = escape(&dependency.label()),
+        );
+    }
+
+    mermaid
+}
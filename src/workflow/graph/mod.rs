@@ -1,19 +1,32 @@
+use std::collections::{BTreeSet, HashMap};
+
 use camino::Utf8Path as Path;
 use petgraph::{
     acyclic::Acyclic,
+    algo::toposort,
     data::Build,
     graph::{DiGraph, NodeIndex},
 };
 
-use crate::{nix_environment::{FlakeOutput, FlakeSource, NixEnvironment, NixRunCommandOptions}, workflow::job::execution::JobExecutionCommand};
+use crate::nix_environment::{FlakeOutput, FlakeSource, NixEnvironment, NixRunCommandOptions};
 
 use super::{
-    job::{execution::ExecutionMethod, Job},
-    specification::{Step, WorkflowSpecification},
+    job::{
+        Job, JobError,
+        execution::{ExecutionRecord, ExecutorSelection, job_execution_command},
+        staleness::{ForcedRerun, RerunReason, RerunTrigger},
+    },
+    specification::{Step, StepIdentity, WorkflowSpecification},
 };
 
+pub mod clean;
 pub mod execution;
+pub mod export;
+pub mod priority;
 pub mod progress;
+pub mod selection;
+pub mod status;
+pub mod submission;
 
 #[derive(Clone, Debug, Copy)]
 pub enum MaybeTransitioning<T> {
@@ -48,8 +61,14 @@ impl From<Job> for MaybeTransitioning<Job> {
     }
 }
 
+// the names of all inputs through which a job consumes the outputs of its parent
+#[derive(Clone, Debug, Default)]
+pub struct JobDependency {
+    pub inputs: BTreeSet<String>,
+}
+
 #[derive(Debug)]
-pub struct JobGraph(DiGraph<MaybeTransitioning<Job>, ()>);
+pub struct JobGraph(DiGraph<MaybeTransitioning<Job>, JobDependency>);
 
 impl JobGraph {
     pub fn new(
@@ -57,16 +76,23 @@ impl JobGraph {
         nix_environment: &Box<dyn NixEnvironment>,
         flake_path: &Path,
         profile: &str,
-        execution_method: ExecutionMethod,
-    ) -> JobGraph {
+        executor_selection: ExecutorSelection,
+    ) -> Result<JobGraph, JobError> {
         fn add_jobs_from_step(
-            graph: &mut Acyclic<DiGraph<MaybeTransitioning<Job>, ()>>,
+            graph: &mut Acyclic<DiGraph<MaybeTransitioning<Job>, JobDependency>>,
+            step_indices: &mut HashMap<StepIdentity, NodeIndex>,
             step: Step,
             nix_environment: &Box<dyn NixEnvironment>,
             flake_path: &Path,
             profile: &str,
-            execution_method: ExecutionMethod,
-        ) -> NodeIndex {
+            executor_selection: ExecutorSelection,
+        ) -> Result<NodeIndex, JobError> {
+            // steps which are consumed by multiple other steps show up once per consumer
+            let identity = step.identity();
+            if let Some(&id) = step_indices.get(&identity) {
+                return Ok(id);
+            }
+
             let run_command = nix_environment.run_command(
                 FlakeOutput::new(
                     FlakeSource::Path(flake_path.to_owned()),
@@ -75,41 +101,83 @@ impl JobGraph {
                 NixRunCommandOptions::default().unbuffered(),
             );
 
-            let command = JobExecutionCommand::new(execution_method, &run_command, step.log, step.execution);
-            let id = graph.add_node(
-                Job::new(command, step.info()).into(),
-            );
-            for (_, input_list) in step.inputs.into_iter() {
+            let info = step.info();
+            let command =
+                job_execution_command(executor_selection, &run_command, &info, step.execution)?;
+            let id = graph.add_node(Job::new(command, info).into());
+            step_indices.insert(identity, id);
+
+            for (input_name, input_list) in step.inputs.into_iter() {
                 for input in input_list.inputs.into_iter() {
                     let parent_id = add_jobs_from_step(
                         graph,
+                        step_indices,
                         input.parent_step,
                         nix_environment,
                         flake_path,
                         profile,
-                        execution_method,
-                    );
-                    graph.add_edge(parent_id, id, ());
+                        executor_selection,
+                    )?;
+                    let mut dependency = graph
+                        .find_edge(parent_id, id)
+                        .and_then(|edge| graph.edge_weight(edge).cloned())
+                        .unwrap_or_default();
+                    dependency.inputs.insert(input_name.clone());
+                    graph.update_edge(parent_id, id, dependency);
                 }
             }
 
-            return id;
+            return Ok(id);
         }
 
         let mut graph = Acyclic::new();
+        let mut step_indices = HashMap::new();
         for (_, targets) in specification.targets.into_iter() {
             for target in targets.into_iter() {
                 add_jobs_from_step(
                     &mut graph,
+                    &mut step_indices,
                     target.parent_step,
                     nix_environment,
                     flake_path,
                     profile,
-                );
+                    executor_selection,
+                )?;
             }
         }
 
-        return JobGraph(graph.into_inner());
+        return Ok(JobGraph(graph.into_inner()));
+    }
+
+    // a job is rerun if it is forced to, if its own outputs are outdated or if any of its parents
+    // is rerun, so parents are resolved first by walking the graph in topological order
+    pub fn resolve_rerun_reasons(
+        &mut self,
+        trigger: RerunTrigger,
+        forced: &ForcedRerun,
+    ) -> Result<(), JobError> {
+        let job_indices =
+            toposort(&self.0, None).expect("job graph was constructed as an acyclic graph");
+        for job_index in job_indices {
+            let rerun_parent = self
+                .parents(job_index)
+                .find(|parent| parent.rerun_reason().is_some())
+                .map(|parent| parent.step().name.clone());
+
+            let Job::Pending(pending) = self.job_mut_stable(job_index) else {
+                continue;
+            };
+            let reason = if forced.includes(&pending.step.name) {
+                Some(RerunReason::Forced)
+            } else if let Some(parent) = rerun_parent {
+                Some(RerunReason::RerunParent(parent))
+            } else {
+                pending.determine_rerun_reason(trigger)?
+            };
+            pending.set_rerun_reason(reason);
+        }
+
+        Ok(())
     }
 
     pub fn job_indices(&self) -> impl Iterator<Item = NodeIndex> {
@@ -122,15 +190,23 @@ impl JobGraph {
             .map(|job| job.as_ref().expect("never called in main execution loop"))
     }
 
-    pub fn job_count(&self) -> u32 {
-        self.0.node_count() as u32
+    pub fn job(&self, index: NodeIndex) -> &Job {
+        self.0
+            .node_weight(index)
+            .expect("job index comes from iteration over existing job indices")
+            .as_ref()
+            .expect("never called in main execution loop")
+    }
+
+    pub fn rerun_job_count(&self) -> u32 {
+        self.count_stable(|job| job.rerun_reason().is_some())
     }
 
     pub fn count_stable(&self, mut f: impl FnMut(&Job) -> bool) -> u32 {
         self.0
             .node_weights()
             .filter_map(|job| job.as_ref().stable())
-            .filter(|job| f(*job))
+            .filter(|job| f(job))
             .count() as u32
     }
 
@@ -140,12 +216,13 @@ impl JobGraph {
             .expect("job index comes from iteration over existing job indices")
     }
 
-    pub fn is_finished(&self) -> bool {
-        self.0.node_weights().all(|job| {
-            job.as_ref()
-                .expect("is_finished is only called outside of job transition")
-                .finished()
-        })
+    fn job_mut_stable(&mut self, index: NodeIndex) -> &mut Job {
+        match self.job_mut(index) {
+            MaybeTransitioning::Stable(job) => job,
+            MaybeTransitioning::Transitioning => {
+                unreachable!("never called in main execution loop")
+            }
+        }
     }
 
     pub fn parents(&self, job_index: NodeIndex) -> impl Iterator<Item = &Job> {
@@ -163,16 +240,162 @@ impl JobGraph {
             })
     }
 
+    pub fn child_indices(&self, job_index: NodeIndex) -> impl Iterator<Item = NodeIndex> {
+        self.0
+            .neighbors_directed(job_index, petgraph::Direction::Outgoing)
+    }
+
+    pub fn topological_jobs(&self) -> impl Iterator<Item = &Job> {
+        toposort(&self.0, None)
+            .expect("job graph was constructed as an acyclic graph")
+            .into_iter()
+            .map(|job_index| self.job(job_index))
+    }
+
+    pub fn print_plan(&self) {
+        for job in self.topological_jobs() {
+            let Job::Pending(pending) = job else {
+                unreachable!("only called before execution")
+            };
+
+            match pending.rerun_reason() {
+                Some(reason) => println!(
+                    "{name}: {reason}\n    {command}",
+                    name = pending.step.name,
+                    command = pending.shell_command()
+                ),
+                None => println!("{name}: up to date", name = pending.step.name),
+            }
+        }
+
+        println!(
+            "{rerun_count} of {job_count} jobs would be executed",
+            rerun_count = self.rerun_job_count(),
+            job_count = self.0.node_count()
+        );
+    }
+
     pub fn print_report(&self) {
+        let mut resubmitted_slurm_jobs = Vec::new();
+        let mut cancelled_slurm_jobs = Vec::new();
         for job in self.0.node_weights() {
             let job = job.as_ref().expect("only called after execution");
+            let report = job.report();
+            if let Some(ExecutionRecord::Slurm {
+                job_id,
+                previous_attempts,
+            }) = report.execution()
+                && !previous_attempts.is_empty()
+            {
+                resubmitted_slurm_jobs.push(format!(
+                    "{step}: {attempts}, finally job {job_id}",
+                    step = job.step().name,
+                    attempts = previous_attempts
+                        .iter()
+                        .map(|attempt| attempt.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ));
+            }
+
             match job {
                 Job::Failed(failed) => {
                     println!("{:?}", miette::Report::new(failed.clone()));
                 }
-                Job::Successful(_) | Job::Terminated(_) => {}
+                Job::Terminated(_) => {
+                    if let Some(ExecutionRecord::Slurm { job_id, .. }) = report.execution() {
+                        cancelled_slurm_jobs.push(format!("{job_id} ({})", job.step().name));
+                    }
+                }
+                Job::Successful(_) => {}
                 _ => unreachable!(),
             }
         }
+
+        if !resubmitted_slurm_jobs.is_empty() {
+            println!(
+                "resubmitted the following slurm jobs:\n\t{}",
+                resubmitted_slurm_jobs.join("\n\t")
+            );
+        }
+
+        if !cancelled_slurm_jobs.is_empty() {
+            println!(
+                "cancelled the following slurm jobs:\n\t{}",
+                cancelled_slurm_jobs.join("\n\t")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use serde_json::{Value, json};
+
+    use crate::nix_environment::NixRunCommand;
+
+    use super::*;
+
+    struct ShellEnvironment;
+    impl NixEnvironment for ShellEnvironment {
+        fn run_command(
+            &self,
+            flake_output: FlakeOutput,
+            _options: NixRunCommandOptions,
+        ) -> Box<dyn NixRunCommand> {
+            Box::new(ShellRunCommand(flake_output.to_string()))
+        }
+    }
+
+    struct ShellRunCommand(String);
+    impl NixRunCommand for ShellRunCommand {
+        fn command(&self) -> Option<&Command> {
+            None
+        }
+
+        fn shell_command(&self) -> String {
+            format!("echo {}", self.0)
+        }
+    }
+
+    fn step(name: &str, inputs: Value) -> Value {
+        json!({
+            "name": name,
+            "inputs": inputs,
+            "outputs": { "out": format!("{name}.txt") },
+            "log": format!("logs/{name}.log"),
+            "run": format!("/nix/store/{name}"),
+        })
+    }
+
+    #[test]
+    fn shared_parents_are_only_added_once() {
+        let input = |name: &str, parent: Value| json!({ "path": format!("{name}.txt"), "parentStep": parent });
+        let top = step("top", json!({}));
+        let left = step("left", json!({ "top": input("top", top.clone()) }));
+        let right = step("right", json!({ "top": input("top", top) }));
+        let bottom = step(
+            "bottom",
+            json!({ "left": input("left", left), "right": input("right", right) }),
+        );
+        let specification: WorkflowSpecification = serde_json::from_value(
+            json!({ "all": { "path": "bottom.txt", "parentStep": bottom } }),
+        )
+        .unwrap();
+
+        let environment: Box<dyn NixEnvironment> = Box::new(ShellEnvironment);
+        let graph = JobGraph::new(
+            specification,
+            &environment,
+            Path::new("."),
+            "default",
+            ExecutorSelection::default(),
+        )
+        .unwrap();
+
+        assert_eq!(graph.0.node_count(), 4);
+        assert_eq!(graph.0.edge_count(), 4);
     }
 }
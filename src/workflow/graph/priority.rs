@@ -0,0 +1,81 @@
+use petgraph::{algo::toposort, graph::NodeIndex};
+use std::{collections::HashMap, time::Duration};
+
+use crate::workflow::{job::JobError, metadata::read_output_metadata};
+
+use super::JobGraph;
+
+// only used if no run of any job was recorded so far
+const DEFAULT_RUNTIME_ESTIMATE: Duration = Duration::from_secs(1);
+
+// ready jobs are started in descending order of their rank, so the declared priority comes first
+// and the longest remaining path through the graph breaks ties
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JobRank {
+    priority: i32,
+    remaining_runtime: Duration,
+}
+
+impl JobGraph {
+    // expects rerun reasons to be resolved
+    pub fn job_ranks(&self) -> Result<HashMap<NodeIndex, JobRank>, JobError> {
+        let runtimes = self.runtime_estimates()?;
+
+        let mut ranks: HashMap<NodeIndex, JobRank> = HashMap::new();
+        let job_indices =
+            toposort(&self.0, None).expect("job graph was constructed as an acyclic graph");
+        for job_index in job_indices.into_iter().rev() {
+            let longest_child_path = self
+                .child_indices(job_index)
+                .map(|child_index| ranks[&child_index].remaining_runtime)
+                .max()
+                .unwrap_or_default();
+            ranks.insert(
+                job_index,
+                JobRank {
+                    priority: self.job(job_index).step().priority,
+                    remaining_runtime: runtimes[&job_index] + longest_child_path,
+                },
+            );
+        }
+
+        Ok(ranks)
+    }
+
+    // jobs which are not rerun finish immediately, while jobs without a recorded run are assumed
+    // to take as long as the average recorded run
+    fn runtime_estimates(&self) -> Result<HashMap<NodeIndex, Duration>, JobError> {
+        let mut recorded_runtimes = HashMap::new();
+        for job_index in self.job_indices() {
+            let job = self.job(job_index);
+            if job.rerun_reason().is_none() {
+                continue;
+            }
+
+            let mut runtime = None;
+            for output in job.step().outputs.iter() {
+                let output_runtime =
+                    read_output_metadata(output)?.map(|metadata| metadata.runtime());
+                runtime = runtime.max(output_runtime);
+            }
+            recorded_runtimes.insert(job_index, runtime);
+        }
+
+        let known_runtimes = recorded_runtimes.values().flatten().collect::<Vec<_>>();
+        let average_runtime = match known_runtimes.len() {
+            0 => DEFAULT_RUNTIME_ESTIMATE,
+            count => known_runtimes.into_iter().sum::<Duration>() / count as u32,
+        };
+
+        Ok(self
+            .job_indices()
+            .map(|job_index| {
+                let runtime = match recorded_runtimes.get(&job_index) {
+                    Some(runtime) => runtime.unwrap_or(average_runtime),
+                    None => Duration::ZERO,
+                };
+                (job_index, runtime)
+            })
+            .collect())
+    }
+}
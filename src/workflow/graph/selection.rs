@@ -0,0 +1,60 @@
+use petgraph::{
+    graph::NodeIndex,
+    visit::{Dfs, Reversed},
+};
+use std::collections::HashSet;
+
+use super::JobGraph;
+
+#[derive(Debug, thiserror::Error)]
+#[error("the job graph contains no step named `{0}`")]
+pub struct UnknownStepError(String);
+
+impl JobGraph {
+    // retains the jobs of the given steps together with all jobs they depend on
+    pub fn retain_until(&mut self, steps: &[String]) -> Result<(), UnknownStepError> {
+        let selected = self.step_job_indices(steps)?;
+
+        let mut retained = HashSet::new();
+        let reversed = Reversed(&self.0);
+        for job_index in selected {
+            let mut dfs = Dfs::new(reversed, job_index);
+            while let Some(ancestor_index) = dfs.next(reversed) {
+                retained.insert(ancestor_index);
+            }
+        }
+
+        self.0
+            .retain_nodes(|_, job_index| retained.contains(&job_index));
+        Ok(())
+    }
+
+    // retains only the jobs of the given steps, which expects their inputs to exist already
+    pub fn retain_only(&mut self, steps: &[String]) -> Result<(), UnknownStepError> {
+        let retained = self.step_job_indices(steps)?;
+        self.0
+            .retain_nodes(|_, job_index| retained.contains(&job_index));
+        Ok(())
+    }
+
+    pub fn ensure_steps_exist(&self, steps: &[String]) -> Result<(), UnknownStepError> {
+        self.step_job_indices(steps).map(|_| ())
+    }
+
+    fn step_job_indices(&self, steps: &[String]) -> Result<HashSet<NodeIndex>, UnknownStepError> {
+        let mut job_indices = HashSet::new();
+        for step in steps {
+            let step_job_indices = self
+                .job_indices()
+                .filter(|job_index| self.job(*job_index).step().name == *step)
+                .collect::<Vec<_>>();
+            if step_job_indices.is_empty() {
+                return Err(UnknownStepError(step.clone()));
+            }
+
+            job_indices.extend(step_job_indices);
+        }
+
+        Ok(job_indices)
+    }
+}
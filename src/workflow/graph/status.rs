@@ -0,0 +1,48 @@
+use indicatif::HumanDuration;
+use std::time::SystemTime;
+
+use crate::workflow::{
+    job::JobError,
+    metadata::{OutputMetadata, read_output_metadata},
+};
+
+use super::JobGraph;
+
+impl JobGraph {
+    // expects rerun reasons to be resolved
+    pub fn print_status(&self) -> Result<(), JobError> {
+        for job in self.topological_jobs() {
+            let step = job.step();
+            match job.rerun_reason() {
+                Some(reason) => println!("{name}: {reason}", name = step.name),
+                None => println!("{name}: up to date", name = step.name),
+            }
+
+            for output in step.outputs.iter() {
+                match read_output_metadata(output)? {
+                    Some(metadata) => {
+                        println!("    {output}: {run}", run = format_last_run(&metadata))
+                    }
+                    None => println!("    {output}: no recorded run"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn format_last_run(metadata: &OutputMetadata) -> String {
+    let runtime = metadata.runtime();
+    let age = SystemTime::now()
+        .duration_since(metadata.end_time)
+        .unwrap_or_default();
+
+    format!(
+        "{status} {age} ago after {runtime} using the {execution}",
+        status = metadata.status,
+        age = HumanDuration(age),
+        runtime = HumanDuration(runtime),
+        execution = metadata.execution,
+    )
+}
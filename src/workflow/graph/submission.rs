@@ -0,0 +1,228 @@
+use indicatif::HumanDuration;
+use petgraph::{Direction, algo::toposort, graph::NodeIndex};
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::workflow::{
+    job::{
+        Job, JobError, PendingJob,
+        execution::{
+            ExecutionMethod, ExecutionRecord, SlurmJobID, SubmittedJobOutcome, cancel_slurm_jobs,
+            query_submitted_job, take_slurm_warning,
+        },
+        staleness::RerunTrigger,
+    },
+    metadata::{RunStatus, read_output_metadata},
+    submission::{SubmissionManifest, SubmittedJob},
+};
+
+use super::JobGraph;
+
+impl JobGraph {
+    // submits every job which has to be rerun at once, with the slurm jobs of its parents as
+    // dependencies, so that nobody has to wait for the jobs to finish; jobs which are still
+    // unfinished from an earlier submission are depended on instead of being submitted again;
+    // expects rerun reasons to be resolved
+    pub fn submit(&self, unfinished: Vec<SubmittedJob>) -> Result<SubmissionManifest, JobError> {
+        let job_indices =
+            toposort(&self.0, None).expect("job graph was constructed as an acyclic graph");
+        let rerun_jobs = job_indices
+            .into_iter()
+            .map(|job_index| (job_index, self.pending_job(job_index)))
+            .filter(|(_, pending)| pending.rerun_reason().is_some())
+            .collect::<Vec<_>>();
+
+        let unsubmittable_steps = rerun_jobs
+            .iter()
+            .filter(|(_, pending)| pending.execution_method() != ExecutionMethod::Slurm)
+            .map(|(_, pending)| pending.step.name.clone())
+            .collect::<Vec<_>>();
+        if !unsubmittable_steps.is_empty() {
+            return Err(JobError::UnsubmittableSteps {
+                steps: unsubmittable_steps,
+            });
+        }
+
+        let submission_time = SystemTime::now();
+        let mut job_ids: HashMap<NodeIndex, SlurmJobID> = HashMap::new();
+        let mut jobs = Vec::new();
+        for (job_index, pending) in rerun_jobs {
+            if let Some(submitted) = find_submitted_job(&unfinished, pending) {
+                job_ids.insert(job_index, submitted.job_id);
+                continue;
+            }
+
+            let dependencies = self
+                .0
+                .neighbors_directed(job_index, Direction::Incoming)
+                .filter_map(|parent_index| job_ids.get(&parent_index).copied())
+                .collect::<Vec<_>>();
+
+            let job_id = match pending.submit(&dependencies) {
+                Ok(job_id) => job_id,
+                Err(err) => {
+                    // the already submitted jobs would run without the rest of the workflow
+                    let submitted = jobs
+                        .iter()
+                        .map(|job: &SubmittedJob| job.job_id)
+                        .collect::<Vec<_>>();
+                    if !submitted.is_empty() {
+                        let _ = cancel_slurm_jobs(&submitted);
+                    }
+                    return Err(err);
+                }
+            };
+            println!(
+                "submitted `{name}` as slurm job {job_id}",
+                name = pending.step.name
+            );
+            if let Some(warning) = take_slurm_warning() {
+                println!("warning: {warning}");
+            }
+
+            job_ids.insert(job_index, job_id);
+            jobs.push(SubmittedJob {
+                step: pending.step.name.clone(),
+                outputs: pending.step.outputs.clone(),
+                submission_time,
+                job_id,
+                dependencies,
+                log: pending.step.log.clone(),
+            });
+        }
+
+        Ok(SubmissionManifest {
+            jobs: unfinished.into_iter().chain(jobs).collect(),
+        })
+    }
+
+    // prints the state of the jobs submitted by earlier invocations and records the runs of
+    // those which finished in the meantime, so that they are up to date from now on; returns the
+    // jobs which are still unfinished, including those which are not part of this graph
+    pub fn reattach_submission(
+        &self,
+        manifest: &SubmissionManifest,
+        rerun_trigger: RerunTrigger,
+    ) -> Result<Vec<SubmittedJob>, JobError> {
+        println!("submitted slurm jobs:");
+
+        let mut reattached = Vec::new();
+        let mut unfinished = Vec::new();
+        for job in self.topological_jobs() {
+            let Job::Pending(pending) = job else {
+                unreachable!("only called before execution")
+            };
+            let Some(submitted) = find_submitted_job(&manifest.jobs, pending) else {
+                continue;
+            };
+            reattached.push(submitted.job_id);
+
+            let age = SystemTime::now()
+                .duration_since(submitted.submission_time)
+                .unwrap_or_default();
+            let outcome = match query_submitted_job(submitted.job_id) {
+                Ok(outcome) => outcome,
+                // whether the job has to be rerun is then only decided by its outputs
+                Err(err) => {
+                    println!(
+                        "    {name}: slurm job {job_id} could not be queried\n{err}",
+                        name = pending.step.name,
+                        job_id = submitted.job_id
+                    );
+                    continue;
+                }
+            };
+            println!(
+                "    {name}: slurm job {job_id}, submitted {age} ago, {outcome}",
+                name = pending.step.name,
+                job_id = submitted.job_id,
+                age = HumanDuration(age)
+            );
+            if let Some(warning) = take_slurm_warning() {
+                println!("    warning: {warning}");
+            }
+
+            let status = match outcome {
+                SubmittedJobOutcome::Unfinished(_) => {
+                    unfinished.push(submitted.clone());
+                    continue;
+                }
+                SubmittedJobOutcome::Unknown => continue,
+                SubmittedJobOutcome::Completed => RunStatus::Successful,
+                SubmittedJobOutcome::Failed(error) => RunStatus::Failed {
+                    error: error.to_string(),
+                },
+            };
+            if recorded_since(pending, submitted.submission_time)? {
+                continue;
+            }
+            pending.record_submitted_run(
+                submitted.submission_time,
+                status,
+                ExecutionRecord::Slurm {
+                    job_id: submitted.job_id,
+                    previous_attempts: Vec::new(),
+                },
+                rerun_trigger,
+            )?;
+        }
+
+        // jobs of other selections are kept track of as well
+        for submitted in manifest.jobs.iter() {
+            if !reattached.contains(&submitted.job_id)
+                && let Ok(SubmittedJobOutcome::Unfinished(_)) =
+                    query_submitted_job(submitted.job_id)
+            {
+                unfinished.push(submitted.clone());
+            }
+        }
+
+        Ok(unfinished)
+    }
+
+    fn pending_job(&self, job_index: NodeIndex) -> &PendingJob {
+        match self.job(job_index) {
+            Job::Pending(pending) => pending,
+            _ => unreachable!("only called before execution"),
+        }
+    }
+}
+
+// replaces an earlier submission, whose jobs would otherwise write the same outputs
+pub fn cancel_submitted_jobs(jobs: &[SubmittedJob]) -> Result<(), JobError> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+
+    let job_ids = jobs.iter().map(|job| job.job_id).collect::<Vec<_>>();
+    cancel_slurm_jobs(&job_ids).map_err(|err| JobError::Submission(err.into()))?;
+    for job in jobs {
+        println!(
+            "cancelled slurm job {job_id} of `{name}`",
+            job_id = job.job_id,
+            name = job.step
+        );
+    }
+    Ok(())
+}
+
+fn find_submitted_job<'a>(
+    jobs: &'a [SubmittedJob],
+    pending: &PendingJob,
+) -> Option<&'a SubmittedJob> {
+    jobs.iter().find(|submitted| {
+        submitted.step == pending.step.name && submitted.outputs == pending.step.outputs
+    })
+}
+
+// runs are recorded once they are noticed to be finished, which should only happen once
+fn recorded_since(pending: &PendingJob, time: SystemTime) -> Result<bool, JobError> {
+    for output in pending.step.outputs.iter() {
+        if let Some(metadata) = read_output_metadata(output)?
+            && metadata.end_time >= time
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
@@ -1,15 +1,28 @@
 use camino::Utf8PathBuf as PathBuf;
 use std::{
     fs::File,
-    process::{Command, Stdio},
-    rc::Rc,
+    os::fd::{AsFd, OwnedFd},
+    process::{Child, Command, Stdio},
+    sync::Arc,
+    time::Duration,
 };
 
 use serde::Deserialize;
 
-use crate::{commands::clone_command, nix_environment::NixRunCommand, utils::IoError};
+use crate::{
+    commands::{AsCommandError, CommandError, clone_command, shell_command},
+    nix_environment::NixRunCommand,
+    utils::IoError,
+    workflow::job::resources::{ResourceGrant, THREADS_ENVIRONMENT_VARIABLE},
+};
+
+use super::{
+    ExecutionError, ExecutionMethod, ExecutionRecord, ExitNotification, JobExecutionChild,
+    JobExecutionCommand, JobExecutionError,
+};
 
-use super::{ExecutionError, JobExecutionChild, JobExecutionCommand, JobExecutionError};
+// only used on systems without pidfd support
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Default, Deserialize)]
 pub struct DefaultExecutionOptions {}
@@ -36,7 +49,18 @@ impl DefaultExecutionCommand {
     }
 }
 impl JobExecutionCommand for DefaultExecutionCommand {
-    fn spawn(mut self: Box<Self>) -> Result<Box<dyn JobExecutionChild>, JobExecutionError> {
+    fn method(&self) -> ExecutionMethod {
+        ExecutionMethod::Default
+    }
+
+    fn shell_command(&self) -> String {
+        shell_command(&self.command)
+    }
+
+    fn spawn(
+        mut self: Box<Self>,
+        grant: &ResourceGrant,
+    ) -> Result<Box<dyn JobExecutionChild>, JobExecutionError> {
         let log_file = File::create(&self.log)
             .map_err(|err| DefaultExecutionError::LogFileCreation(self.log.clone(), err.into()))?;
         let log_file_stderr = log_file
@@ -47,25 +71,86 @@ impl JobExecutionCommand for DefaultExecutionCommand {
             .command
             .stdout(Stdio::from(log_file))
             .stderr(Stdio::from(log_file_stderr))
+            .env(THREADS_ENVIRONMENT_VARIABLE, grant.threads.to_string())
             .spawn()
             .map_err(|err| {
                 DefaultExecutionError::Spawn(format!("{:?}", self.command), err.into())
             })?;
 
-        Ok(Box::new(DefaultExecutionChild::new(child)))
+        Ok(Box::new(DefaultExecutionChild::new(child, self.command)))
     }
 }
 
 #[derive(Debug)]
 pub struct DefaultExecutionChild {
-    child: std::process::Child,
+    child: Child,
+    command: Command,
+    pidfd: Option<OwnedFd>,
 }
 impl DefaultExecutionChild {
-    pub fn new(child: std::process::Child) -> Self {
-        DefaultExecutionChild { child }
+    pub fn new(child: Child, command: Command) -> Self {
+        DefaultExecutionChild {
+            pidfd: open_pidfd(&child),
+            child,
+            command,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_pidfd(child: &Child) -> Option<OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id() as libc::pid_t, 0) };
+    // the file descriptor was just created, so we are its only owner
+    (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_pidfd(_child: &Child) -> Option<OwnedFd> {
+    None
+}
+
+impl JobExecutionChild for DefaultExecutionChild {
+    fn exit_notification(&self) -> ExitNotification<'_> {
+        match &self.pidfd {
+            Some(pidfd) => ExitNotification::Readable(pidfd.as_fd()),
+            None => ExitNotification::Polling(CHILD_POLL_INTERVAL),
+        }
+    }
+
+    fn try_wait(&mut self) -> Result<bool, JobExecutionError> {
+        Ok(self
+            .child
+            .try_wait()
+            .map_err(|err| DefaultExecutionError::Wait(format!("{:?}", self.command), err.into()))?
+            .is_some())
+    }
+
+    fn wait(&mut self) -> Result<(), JobExecutionError> {
+        let status = self.child.wait().map_err(|err| {
+            DefaultExecutionError::Wait(format!("{:?}", self.command), err.into())
+        })?;
+
+        Ok(status
+            .as_command_result(&self.command)
+            .map_err(DefaultExecutionError::Execution)?)
+    }
+
+    fn kill(&mut self) -> Result<(), JobExecutionError> {
+        self.child.kill().map_err(|err| {
+            DefaultExecutionError::Kill(format!("{:?}", self.command), err.into())
+        })?;
+        // reap the child to not leave a zombie process behind
+        let _ = self.child.wait();
+
+        Ok(())
+    }
+
+    fn record(&self) -> Option<ExecutionRecord> {
+        Some(ExecutionRecord::Default)
     }
 }
-impl JobExecutionChild for DefaultExecutionChild {}
 
 #[derive(Clone, Debug, thiserror::Error)]
 enum DefaultExecutionError {
@@ -77,10 +162,19 @@ enum DefaultExecutionError {
 
     #[error("failed to spawn `{0}`\n{1}")]
     Spawn(String, IoError),
+
+    #[error("failed to poll `{0}`\n{1}")]
+    Wait(String, IoError),
+
+    #[error("failed to kill `{0}`\n{1}")]
+    Kill(String, IoError),
+
+    #[error("{0}")]
+    Execution(CommandError),
 }
 impl ExecutionError for DefaultExecutionError {}
 impl From<DefaultExecutionError> for JobExecutionError {
     fn from(error: DefaultExecutionError) -> Self {
-        JobExecutionError(Rc::new(error))
+        JobExecutionError(Arc::new(error))
     }
 }
@@ -1,19 +1,27 @@
-use camino::Utf8PathBuf as PathBuf;
 use clap::ValueEnum;
 use default::{DefaultExecutionCommand, DefaultExecutionOptions};
 use derive_more::Display;
-use serde::Deserialize;
-use slurm::{SlurmExecutionCommand, options::SlurmExecutionOptions};
-use std::{error::Error, fmt::Debug, rc::Rc};
+use serde::{Deserialize, Deserializer, Serialize, de::Error as _};
+use slurm::{
+    SlurmExecutionChild, SlurmExecutionCommand, options::SlurmExecutionOptions,
+    submit_slurm_children, submit_slurm_group,
+};
+use std::{error::Error, fmt::Debug, os::fd::BorrowedFd, sync::Arc, time::Duration};
 
-use crate::nix_environment::NixRunCommand;
+use crate::{nix_environment::NixRunCommand, workflow::specification::StepInfo};
 
-use super::JobError;
+use super::{JobError, resources::ResourceGrant};
 
 mod default;
 mod slurm;
 
-#[derive(Display, Default, Clone, Debug, ValueEnum)]
+pub use slurm::{
+    SlurmAttempt, SlurmJobID, SubmittedJobOutcome, cancel_slurm_jobs, query_submitted_job,
+    set_slurm_grace_period, set_slurm_poll_interval, take_slurm_warning,
+};
+
+#[derive(Display, Default, Clone, Copy, Debug, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
 pub enum ExecutionMethod {
     #[default]
     #[display("default")]
@@ -22,126 +30,296 @@ pub enum ExecutionMethod {
     Slurm,
 }
 
-#[derive(Debug, Default, Deserialize)]
+// the executor of a step is the one chosen by the step itself, unless it is overridden
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExecutorSelection {
+    pub default: ExecutionMethod,
+    pub overriding: bool,
+}
+
+#[derive(Debug, Default)]
 pub struct ExecutionOptions {
+    selected: Option<ExecutionMethod>,
     default: DefaultExecutionOptions,
     slurm: Option<SlurmExecutionOptions>,
 }
+impl ExecutionOptions {
+    fn method(&self, selection: ExecutorSelection) -> ExecutionMethod {
+        match self.selected {
+            Some(selected) if !selection.overriding => selected,
+            _ => selection.default,
+        }
+    }
+}
 
-pub trait JobExecutionCommand: Debug {
-    fn new(
-        method: ExecutionMethod,
-        target: &Box<dyn NixRunCommand>,
-        log: PathBuf,
-        options: ExecutionOptions,
-    ) -> Box<dyn JobExecutionCommand>
-    where
-        Self: Sized,
-    {
-        match method {
-            ExecutionMethod::Default => Box::new(DefaultExecutionCommand::new(
-                target,
-                log,
-                options.default,
-            )),
-            ExecutionMethod::Slurm => Box::new(SlurmExecutionCommand::new(
-                target,
-                log,
-                options
-                    .slurm
-                    .ok_or(JobError::UnprovidedExecutorUsage(method))?,
-            )),
+// steps choose their executor with `nixflow.executors.<executor>`, which results in the options
+// of that executor together with its `id`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "id", rename_all = "snake_case")]
+enum SelectedExecutor {
+    Default(DefaultExecutionOptions),
+    Slurm(Box<SlurmExecutionOptions>),
+}
+
+// steps which do not choose an executor can still provide options for every executor, to be
+// used with `--executor`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PerExecutorOptions {
+    #[serde(default)]
+    default: DefaultExecutionOptions,
+    slurm: Option<SlurmExecutionOptions>,
+}
+
+impl<'de> Deserialize<'de> for ExecutionOptions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let options = if value.get("id").is_none() {
+            let options = PerExecutorOptions::deserialize(value).map_err(D::Error::custom)?;
+            Self {
+                selected: None,
+                default: options.default,
+                slurm: options.slurm,
+            }
+        } else {
+            match SelectedExecutor::deserialize(value).map_err(D::Error::custom)? {
+                SelectedExecutor::Default(default) => Self {
+                    selected: Some(ExecutionMethod::Default),
+                    default,
+                    slurm: None,
+                },
+                SelectedExecutor::Slurm(slurm) => Self {
+                    selected: Some(ExecutionMethod::Slurm),
+                    default: DefaultExecutionOptions::default(),
+                    slurm: Some(*slurm),
+                },
+            }
+        };
+
+        if let Some(slurm) = &options.slurm {
+            slurm.validate().map_err(D::Error::custom)?;
         }
+
+        return Ok(options);
     }
+}
 
-    fn spawn(self: Box<Self>) -> Result<Box<dyn JobExecutionChild>, JobExecutionError>;
+pub fn job_execution_command(
+    selection: ExecutorSelection,
+    target: &Box<dyn NixRunCommand>,
+    step: &StepInfo,
+    options: ExecutionOptions,
+) -> Result<Box<dyn JobExecutionCommand>, JobError> {
+    let method = options.method(selection);
+    Ok(match method {
+        ExecutionMethod::Default => Box::new(DefaultExecutionCommand::new(
+            target,
+            step.log.clone(),
+            options.default,
+        )),
+        ExecutionMethod::Slurm => Box::new(SlurmExecutionCommand::new(
+            target,
+            step,
+            options
+                .slurm
+                .ok_or(JobError::UnprovidedExecutorUsage(method))?,
+        )?),
+    })
+}
+
+pub trait JobExecutionCommand: Debug {
+    fn method(&self) -> ExecutionMethod;
+    fn shell_command(&self) -> String;
+
+    // only slurm jobs which were not yet submitted can be grouped into a single allocation
+    fn slurm_command(&self) -> Option<&SlurmExecutionCommand> {
+        None
+    }
+
+    // the slurm allocation the job was submitted with ahead of time as part of a group, which
+    // has to be cancelled even if the job never started
+    fn slurm_allocation(&self) -> Option<SlurmJobID> {
+        None
+    }
+
+    fn spawn(
+        self: Box<Self>,
+        grant: &ResourceGrant,
+    ) -> Result<Box<dyn JobExecutionChild>, JobExecutionError>;
+
+    // submits the job without waiting for it, so that it only starts once all dependencies
+    // completed successfully
+    fn submit(
+        &self,
+        _grant: &ResourceGrant,
+        _dependencies: &[SlurmJobID],
+    ) -> Result<SlurmJobID, JobError> {
+        Err(JobError::UnsupportedSubmission(self.method()))
+    }
+}
+
+// how the scheduler learns that a child might have finished, so that it does not have to poll
+// every child all the time
+pub enum ExitNotification<'c> {
+    // becomes readable once the child exited
+    Readable(BorrowedFd<'c>),
+    Polling(Duration),
 }
 
 pub trait JobExecutionChild: Debug {
+    fn exit_notification(&self) -> ExitNotification<'_>;
     fn try_wait(&mut self) -> Result<bool, JobExecutionError>;
     fn wait(&mut self) -> Result<(), JobExecutionError>;
+    fn kill(&mut self) -> Result<(), JobExecutionError>;
+    // nothing for jobs which never started, e.g. since their slurm submission failed
+    fn record(&self) -> Option<ExecutionRecord>;
+
+    // what the job is currently waiting for, if anything, e.g. a slurm job pending in the queue
+    fn status(&self) -> Option<String> {
+        None
+    }
+
+    // something the user should know about, which does not fail the job, e.g. that the slurm
+    // accounting is unavailable
+    fn take_warning(&mut self) -> Option<String> {
+        None
+    }
+
+    // slurm jobs are submitted in the background, which has to finish before they can be cancelled
+    fn await_submission(&mut self) {}
+
+    // slurm children are only submitted once all ready jobs were started, so that they can be
+    // submitted together
+    fn unsubmitted_slurm_child(&mut self) -> Option<&mut SlurmExecutionChild> {
+        None
+    }
+}
+
+// how a job was executed, as recorded in the run metadata
+#[derive(Clone, Debug, Display, Deserialize, Serialize)]
+#[serde(tag = "executor", rename_all = "snake_case")]
+pub enum ExecutionRecord {
+    #[display("default executor")]
+    Default,
+    #[display(
+        "slurm executor (job {job_id}{})",
+        format_previous_attempts(previous_attempts)
+    )]
+    Slurm {
+        job_id: SlurmJobID,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        previous_attempts: Vec<SlurmAttempt>,
+    },
+    #[display("`--touch` flag, without execution")]
+    Touch,
+}
+
+fn format_previous_attempts(previous_attempts: &[SlurmAttempt]) -> String {
+    if previous_attempts.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        ", previously {}",
+        previous_attempts
+            .iter()
+            .map(|attempt| attempt.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+// whether two unsubmitted slurm jobs can run within the same group allocation
+pub fn shares_slurm_allocation(
+    command: &Box<dyn JobExecutionCommand>,
+    other: &Box<dyn JobExecutionCommand>,
+) -> bool {
+    match (command.slurm_command(), other.slurm_command()) {
+        (Some(command), Some(other)) => command.shares_allocation_with(other),
+        _ => false,
+    }
 }
 
-pub trait ExecutionError: Error {}
+// the jobs are given in topological order together with the indices of their parents within the
+// group and their grants, the returned commands let the jobs follow their part of the allocation
+pub fn submit_job_group(
+    members: &[(&Box<dyn JobExecutionCommand>, Vec<usize>)],
+    grants: &[ResourceGrant],
+) -> Vec<Box<dyn JobExecutionCommand>> {
+    let members = members
+        .iter()
+        .map(|(command, parents)| {
+            (
+                command
+                    .slurm_command()
+                    .expect("only unsubmitted slurm jobs are grouped"),
+                parents.clone(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    submit_slurm_group(&members, grants)
+        .into_iter()
+        .map(|command| Box::new(command) as Box<dyn JobExecutionCommand>)
+        .collect()
+}
+
+// all other children were already started when they were spawned
+pub fn submit_children(children: Vec<&mut Box<dyn JobExecutionChild>>) {
+    let slurm_children = children
+        .into_iter()
+        .filter_map(|child| child.unsubmitted_slurm_child())
+        .collect::<Vec<_>>();
+    if !slurm_children.is_empty() {
+        submit_slurm_children(slurm_children);
+    }
+}
+
+// slurm jobs are cancelled with a single `scancel`, all other children are killed one by one
+pub fn kill_children(
+    mut children: Vec<&mut Box<dyn JobExecutionChild>>,
+) -> Vec<Result<(), JobExecutionError>> {
+    for child in children.iter_mut() {
+        child.await_submission();
+    }
+    let slurm_job_ids = children
+        .iter()
+        .filter_map(|child| match child.record() {
+            Some(ExecutionRecord::Slurm { job_id, .. }) => Some(job_id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let slurm_cancellation = match slurm_job_ids.is_empty() {
+        true => Ok(()),
+        false => cancel_slurm_jobs(&slurm_job_ids).map_err(JobExecutionError::from),
+    };
+
+    children
+        .iter_mut()
+        .map(|child| match child.record() {
+            Some(ExecutionRecord::Slurm { .. }) => slurm_cancellation.clone(),
+            _ => child.kill(),
+        })
+        .collect()
+}
+
+pub trait ExecutionError: Error + Send + Sync {
+    // errors which describe why the job itself failed map to a more specific job error
+    fn job_error(&self) -> Option<JobError> {
+        None
+    }
+}
 
 #[derive(Clone, Debug, Display)]
 #[display("{}", self.0.to_string())]
-pub struct JobExecutionError(Rc<dyn ExecutionError>);
+pub struct JobExecutionError(Arc<dyn ExecutionError>);
+impl JobExecutionError {
+    pub fn job_error(&self) -> Option<JobError> {
+        self.0.job_error()
+    }
+}
 impl Error for JobExecutionError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         self.0.source()
     }
 }
-
-//#[derive(Debug)]
-//pub enum JobExecutionOptions {
-//    Default(DefaultExecutionOptions),
-//    Slurm(SlurmExecutionOptions),
-//}
-//
-//#[derive(Debug)]
-//pub struct JobExecutionCommand {
-//    command: Command,
-//    log: Option<File>,
-//    options: JobExecutionOptions,
-//}
-//
-//impl JobExecutionCommand {
-//    pub fn new(
-//        method: ExecutionMethod,
-//        target: &Box<dyn NixRunCommand>,
-//        options: ExecutionOptions,
-//    ) -> Result<Self, ExecutionError> {
-//        Ok(match method {
-//            ExecutionMethod::Default => slurm_execution_command(
-//                target,
-//                options
-//                    .slurm
-//                    .ok_or(ExecutionError::UnprovidedExecutorUsage(method))?,
-//            ),
-//            ExecutionMethod::Slurm => default_execution_command(target, options.default),
-//        })
-//    }
-//
-//    pub fn log(&mut self, file: File) {
-//        self.log = Some(file);
-//    }
-//
-//    pub fn spawn(mut self) -> Result<JobExecutionChild, ExecutionError> {
-//        if let Some(log) = self.log {
-//            let log_stderr = log
-//                .try_clone()
-//                .map_err(|err| ExecutionError::LogFileDuplication(err.into()))?;
-//
-//            self.command
-//                .stdout(Stdio::from(log))
-//                .stderr(Stdio::from(log_stderr))
-//        } else {
-//            self.command.stdout(Stdio::piped()).stderr(Stdio::piped())
-//        };
-//
-//        let child = self
-//            .command
-//            .spawn()
-//            .map_err(|err| ExecutionError::Spawn(format!("{:?}", self.command), err.into()))?;
-//
-//        Ok(JobExecutionChild::new(child, self.command, self.options))
-//    }
-//}
-//
-//pub enum JobExecutionChild {
-//    Default(DefaultExecutionChild),
-//    Slurm(SlurmExecutionChild),
-//}
-//impl JobExecutionChild {
-//    pub fn new(child: Child, command: Command, options: JobExecutionOptions) -> Self {
-//        match options {
-//            JobExecutionOptions::Default(options) => {
-//                JobExecutionChild::Default(DefaultExecutionChild::new(child, command, options))
-//            }
-//            JobExecutionOptions::Slurm(options) => {
-//                JobExecutionChild::Slurm(SlurmExecutionChild::new(child, command, options))
-//            }
-//        }
-//    }
-//}
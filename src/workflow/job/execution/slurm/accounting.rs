@@ -0,0 +1,221 @@
+use std::{
+    collections::HashMap,
+    process::{Command, Stdio},
+};
+
+use crate::commands::{AsCommandError, CommandError, OutputUtf8};
+
+use super::{SlurmError, SlurmJobID, retry::retry_transient, state::JobState};
+
+const ACCOUNTING_FIELDS: &str = "JobID,State,ExitCode,Elapsed,MaxRSS,NodeList";
+
+// what slurm accounting knows about a job once it finished
+#[derive(Clone, Debug)]
+pub struct JobAccounting {
+    pub state: JobState,
+    pub exit_code: i32,
+    pub signal: i32,
+    pub elapsed: String,
+    pub max_rss: Option<String>,
+    pub node_list: String,
+}
+impl JobAccounting {
+    // the lines of every job are grouped by their job ID, with the job steps (e.g. `123.batch`)
+    // following the line of the allocation itself
+    fn from_accounting_output(output: &str) -> Result<HashMap<SlurmJobID, Self>, String> {
+        let mut jobs: Vec<(&str, Vec<&str>)> = Vec::new();
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            let [job_id, ..] = split_fields(line)?;
+            let job_id = job_id.split_once('.').map_or(job_id, |(job_id, _)| job_id);
+            match jobs.iter_mut().find(|(job, _)| *job == job_id) {
+                Some((_, lines)) => lines.push(line),
+                None => jobs.push((job_id, vec![line])),
+            }
+        }
+
+        let mut accounting = HashMap::new();
+        for (job_id, lines) in jobs {
+            let job = Self::from_job_lines(lines.into_iter())?;
+            for job_id in SlurmJobID::expand(job_id)? {
+                accounting.insert(job_id, job.clone());
+            }
+        }
+        Ok(accounting)
+    }
+
+    // the first line describes the allocation, while the memory usage is only recorded for the
+    // job steps (e.g. `batch`) in the following lines
+    fn from_job_lines<'a>(mut lines: impl Iterator<Item = &'a str>) -> Result<Self, String> {
+        let allocation = lines.next().expect("every job has at least one line");
+        let [_, state, exit_code, elapsed, _, node_list] = split_fields(allocation)?;
+        let (exit_code, signal) = exit_code.split_once(':').ok_or(format!(
+            "expected exit code `{exit_code}` to be of the form `<code>:<signal>`"
+        ))?;
+
+        let max_rss = lines
+            .map(|step| split_fields(step).map(|[_, _, _, _, max_rss, _]| max_rss))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|max_rss| !max_rss.is_empty())
+            .max_by_key(|max_rss| parse_memory(max_rss))
+            .map(|max_rss| max_rss.to_owned());
+
+        Ok(Self {
+            state: JobState::from_accounting_state(state)?,
+            exit_code: exit_code
+                .parse()
+                .map_err(|err| format!("failed to parse exit code `{exit_code}`\n{err}"))?,
+            signal: signal
+                .parse()
+                .map_err(|err| format!("failed to parse signal `{signal}`\n{err}"))?,
+            elapsed: elapsed.to_owned(),
+            max_rss,
+            node_list: node_list.to_owned(),
+        })
+    }
+}
+
+fn split_fields(line: &str) -> Result<[&str; 6], String> {
+    line.split('|')
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| format!("expected `{line}` to consist of the fields `{ACCOUNTING_FIELDS}`"))
+}
+
+// memory sizes are reported with a unit suffix like `K` or `M`, which is only needed to compare
+// the sizes of different job steps
+fn parse_memory(size: &str) -> u64 {
+    let (number, multiplier) = match size.chars().last() {
+        Some('K') => (&size[..size.len() - 1], 1u64 << 10),
+        Some('M') => (&size[..size.len() - 1], 1u64 << 20),
+        Some('G') => (&size[..size.len() - 1], 1u64 << 30),
+        Some('T') => (&size[..size.len() - 1], 1u64 << 40),
+        _ => (size, 1),
+    };
+    number
+        .parse::<f64>()
+        .map(|number| (number * multiplier as f64) as u64)
+        .unwrap_or(0)
+}
+
+// returns nothing if the job is not (yet) known to the accounting
+pub fn query_job_accounting(job_id: SlurmJobID) -> Result<Option<JobAccounting>, SlurmError> {
+    Ok(retry_transient(|| query_jobs_accounting(&[job_id]))?.remove(&job_id))
+}
+
+// jobs which are not (yet) known to the accounting are left out
+pub fn query_jobs_accounting(
+    job_ids: &[SlurmJobID],
+) -> Result<HashMap<SlurmJobID, JobAccounting>, SlurmError> {
+    let mut command = Command::new("sacct");
+    command
+        .arg("--jobs")
+        .arg(
+            job_ids
+                .iter()
+                .map(|job_id| job_id.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .arg("--parsable2")
+        .arg("--noheader")
+        .arg(format!("--format={ACCOUNTING_FIELDS}"));
+
+    let output: OutputUtf8 = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| CommandError::new_io(&command, err))
+        .map_err(SlurmError::JobAccountingQuery)?
+        .into();
+
+    output
+        .status
+        .as_piped_command_result(&command, &output.stdout, &output.stderr)
+        .map_err(SlurmError::JobAccountingQuery)?;
+
+    JobAccounting::from_accounting_output(&output.stdout).map_err(|error| {
+        SlurmError::JobAccountingParsing {
+            command: format!("{command:?}"),
+            error,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_id(job: u64, array_task: Option<u32>) -> SlurmJobID {
+        SlurmJobID { job, array_task }
+    }
+
+    const ACCOUNTING_OUTPUT: &str = "\
+1234|COMPLETED|0:0|00:01:02||node01
+1234.batch|COMPLETED|0:0|00:01:02|1234K|node01
+1234.extern|COMPLETED|0:0|00:01:02|100K|node01
+1235|CANCELLED by 1000|0:15|00:00:10||node02
+1235.batch|CANCELLED|0:15|00:00:11|2G|node02
+1235.extern|COMPLETED|0:0|00:00:11|0|node02
+1236_3|FAILED|1:0|00:00:05||node03
+1236_3.batch|FAILED|1:0|00:00:05||node03
+1236_[4-6%2]|PENDING|0:0|00:00:00||None assigned
+";
+
+    #[test]
+    fn accounting_output_is_grouped_by_job() {
+        let accounting = JobAccounting::from_accounting_output(ACCOUNTING_OUTPUT).unwrap();
+        assert_eq!(accounting.len(), 6);
+
+        let completed = &accounting[&job_id(1234, None)];
+        assert!(matches!(completed.state, JobState::Completed));
+        assert_eq!(completed.exit_code, 0);
+        assert_eq!(completed.elapsed, "00:01:02");
+        assert_eq!(completed.max_rss.as_deref(), Some("1234K"));
+        assert_eq!(completed.node_list, "node01");
+    }
+
+    #[test]
+    fn cancelled_jobs_keep_their_signal() {
+        let accounting = JobAccounting::from_accounting_output(ACCOUNTING_OUTPUT).unwrap();
+
+        let cancelled = &accounting[&job_id(1235, None)];
+        assert!(matches!(cancelled.state, JobState::Cancelled));
+        assert_eq!(cancelled.signal, 15);
+        assert_eq!(cancelled.max_rss.as_deref(), Some("2G"));
+    }
+
+    #[test]
+    fn array_tasks_without_memory_usage() {
+        let accounting = JobAccounting::from_accounting_output(ACCOUNTING_OUTPUT).unwrap();
+
+        let failed = &accounting[&job_id(1236, Some(3))];
+        assert!(matches!(failed.state, JobState::Failed));
+        assert_eq!(failed.exit_code, 1);
+        assert_eq!(failed.max_rss, None);
+    }
+
+    #[test]
+    fn compressed_array_tasks_are_expanded() {
+        let accounting = JobAccounting::from_accounting_output(ACCOUNTING_OUTPUT).unwrap();
+
+        for task in 4..=6 {
+            let pending = &accounting[&job_id(1236, Some(task))];
+            assert!(matches!(pending.state, JobState::Pending { reason: None }));
+        }
+    }
+
+    #[test]
+    fn malformed_accounting_output_is_rejected() {
+        assert!(JobAccounting::from_accounting_output("1234|COMPLETED|0:0").is_err());
+        assert!(JobAccounting::from_accounting_output("1234|COMPLETED|0|00:00:01||n").is_err());
+        assert!(JobAccounting::from_accounting_output("1234|BOGUS|0:0|00:00:01||n").is_err());
+    }
+
+    #[test]
+    fn memory_sizes_are_compared_by_unit() {
+        assert!(parse_memory("2G") > parse_memory("1234K"));
+        assert!(parse_memory("1.5M") > parse_memory("1024K"));
+        assert_eq!(parse_memory("0"), 0);
+    }
+}
@@ -0,0 +1,458 @@
+use camino::Utf8PathBuf as PathBuf;
+use std::{sync::Arc, thread, time::Instant};
+
+use crate::workflow::job::resources::{ResourceGrant, THREADS_ENVIRONMENT_VARIABLE};
+
+use super::{
+    super::{
+        ExecutionMethod, ExecutionRecord, ExitNotification, JobExecutionChild, JobExecutionCommand,
+        JobExecutionError,
+    },
+    SlurmError, SlurmExecutionCommand, SlurmJobID,
+    accounting::JobAccounting,
+    cancel_slurm_jobs,
+    options::{MemorySize, SlurmExecutionOptions},
+    pending_status,
+    polling::{PolledJob, follow_slurm_job, polled_job, slurm_poll_interval, unfollow_slurm_job},
+    retry::Background,
+    sbatch_command,
+    scratch::quote,
+    slurm_output_option,
+    state::JobState,
+    submit_execution_script, take_slurm_warning,
+};
+
+// every job of a group writes its exit code to a file of its own in this directory, named after
+// the allocation, once it finished
+const GROUP_STATUS_DIRECTORY: &str = ".nixflow/groups";
+
+// written instead of an exit code for jobs which did not run, since one of their parents within
+// the group failed
+const SKIPPED_MARKER: &str = "skipped";
+
+// sbatch runs in the background, since it is retried for as long as it fails transiently
+#[derive(Debug)]
+pub struct SlurmGroupAllocation {
+    job_id: Background<SlurmJobID>,
+    submission_time: Instant,
+}
+impl SlurmGroupAllocation {
+    fn status_directory(job_id: SlurmJobID) -> PathBuf {
+        PathBuf::from(GROUP_STATUS_DIRECTORY).join(job_id.to_string())
+    }
+
+    fn exit_code_path(job_id: SlurmJobID, member: usize) -> PathBuf {
+        Self::status_directory(job_id).join(member.to_string())
+    }
+}
+// the exit codes are only removed once no member is around anymore, which might still have to
+// read its exit code
+impl Drop for SlurmGroupAllocation {
+    fn drop(&mut self) {
+        if let Ok(job_id) = self.job_id.outcome() {
+            let _ = std::fs::remove_dir_all(Self::status_directory(job_id));
+        }
+    }
+}
+
+// members are given in topological order together with the indices of their parents within the
+// group, members only run if all of their parents within the group succeeded; the submission
+// fails the members only once they are polled
+pub fn submit_slurm_group(
+    members: &[(&SlurmExecutionCommand, Vec<usize>)],
+    grants: &[ResourceGrant],
+) -> Vec<SlurmGroupMemberCommand> {
+    let (leader, _) = members.first().expect("groups consist of at least one job");
+
+    let batches = group_batches(members, leader.options.group_parallelism);
+    let options = allocation_options(members, &batches);
+    let mut command = sbatch_command(&options, &[]);
+
+    let script = group_script(members, &batches, grants);
+    let allocation = Arc::new(SlurmGroupAllocation {
+        job_id: Background::spawn(move || {
+            command.arg(slurm_output_option("%j.log")?);
+            submit_execution_script(command, &script)
+        }),
+        submission_time: Instant::now(),
+    });
+    members
+        .iter()
+        .enumerate()
+        .map(|(index, (member, _))| SlurmGroupMemberCommand {
+            allocation: allocation.clone(),
+            member: index,
+            shell_command: member.shell_command.clone(),
+        })
+        .collect()
+}
+
+// members of the same depth within the group do not depend on each other, so they are run in
+// batches of the allowed parallelism, one batch after the other
+fn group_batches(
+    members: &[(&SlurmExecutionCommand, Vec<usize>)],
+    parallelism: u32,
+) -> Vec<Vec<usize>> {
+    let mut depths: Vec<usize> = Vec::new();
+    for (_, parents) in members.iter() {
+        let depth = parents
+            .iter()
+            .map(|parent| depths[*parent] + 1)
+            .max()
+            .unwrap_or(0);
+        depths.push(depth);
+    }
+
+    let max_depth = depths.iter().copied().max().unwrap_or(0);
+    (0..=max_depth)
+        .flat_map(|depth| {
+            let level = (0..members.len())
+                .filter(|member| depths[*member] == depth)
+                .collect::<Vec<_>>();
+            level
+                .chunks(parallelism.max(1) as usize)
+                .map(|batch| batch.to_vec())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// the allocation has to fit the largest batch and has to last long enough to run all batches, the
+// options of the first job apply otherwise, which all members share (see `shares_allocation_with`)
+fn allocation_options(
+    members: &[(&SlurmExecutionCommand, Vec<usize>)],
+    batches: &[Vec<usize>],
+) -> SlurmExecutionOptions {
+    let (leader, _) = members.first().expect("groups consist of at least one job");
+    let batch_options = |batch: &Vec<usize>| {
+        batch
+            .iter()
+            .map(|member| &members[*member].0.options)
+            .collect::<Vec<_>>()
+    };
+
+    let mut options = leader.options.clone();
+    options.runtime = batches
+        .iter()
+        .map(|batch| {
+            batch_options(batch)
+                .iter()
+                .map(|options| options.runtime)
+                .max()
+                .unwrap_or_default()
+        })
+        .sum();
+    let batch_maximum = |count: fn(&SlurmExecutionOptions) -> u16| {
+        batches
+            .iter()
+            .map(|batch| {
+                batch_options(batch)
+                    .iter()
+                    .map(|options| count(options))
+                    .fold(0, u16::saturating_add)
+            })
+            .max()
+            .unwrap_or_default()
+    };
+    options.cpu_count = batch_maximum(|options| options.cpu_count);
+    options.gpu_count = batch_maximum(|options| options.gpu_count);
+
+    // members without any memory size get slurm's default, which is not accounted for, while
+    // members which need all available memory make the allocation need it as well
+    let member_memory = |options: &SlurmExecutionOptions| match options.memory_per_cpu {
+        Some(memory) => memory
+            .kilo_bytes()
+            .map(|kilo_bytes| kilo_bytes * options.cpu_count as u64),
+        None => options.memory_size.map_or(Some(0), MemorySize::kilo_bytes),
+    };
+    let memory = members
+        .iter()
+        .map(|(member, _)| member_memory(&member.options))
+        .collect::<Option<Vec<_>>>();
+    let specified = members.iter().any(|(member, _)| {
+        member.options.memory_size.is_some() || member.options.memory_per_cpu.is_some()
+    });
+    (options.memory_size, options.memory_per_cpu) = match memory {
+        None => (Some(MemorySize::AllAvailable), None),
+        Some(_) if !specified => (None, None),
+        Some(memory) => {
+            let kilo_bytes = batches
+                .iter()
+                .map(|batch| batch.iter().map(|member| memory[*member]).sum::<u64>())
+                .max()
+                .unwrap_or_default();
+            (Some(MemorySize::from_kilo_bytes(kilo_bytes)), None)
+        }
+    };
+
+    options
+}
+
+// every member is told the number of threads it declared itself
+fn group_script(
+    members: &[(&SlurmExecutionCommand, Vec<usize>)],
+    batches: &[Vec<usize>],
+    grants: &[ResourceGrant],
+) -> String {
+    let mut script = format!(
+        "#!/bin/sh\n\
+        status_directory=\"{GROUP_STATUS_DIRECTORY}/$SLURM_JOB_ID\"\n\
+        mkdir -p \"$status_directory\"\n"
+    );
+    for batch in batches {
+        for member in batch {
+            let (command, parents) = &members[*member];
+            let condition = match parents.is_empty() {
+                true => "true".to_owned(),
+                false => parents
+                    .iter()
+                    .map(|parent| {
+                        format!("[ \"$(cat \"$status_directory/{parent}\" 2>/dev/null)\" = 0 ]")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" && "),
+            };
+            // jobs whose parents failed are marked as skipped, so that they do not wait for
+            // the allocation to end
+            script += &format!(
+                "if {condition}; then\n    \
+                    (\n        \
+                        export {THREADS_ENVIRONMENT_VARIABLE}={threads}\n        \
+                        ( {shell_command} ) >{log} 2>&1\n        \
+                        echo $? >\"$status_directory/{member}.tmp\"\n        \
+                        mv \"$status_directory/{member}.tmp\" \"$status_directory/{member}\"\n    \
+                    ) &\n\
+                else\n    \
+                    echo {SKIPPED_MARKER} >\"$status_directory/{member}.tmp\"\n    \
+                    mv \"$status_directory/{member}.tmp\" \"$status_directory/{member}\"\n\
+                fi\n",
+                threads = grants[*member].threads,
+                shell_command = command.shell_command,
+                log = quote(&command.log),
+            );
+        }
+        script += "wait\n";
+    }
+
+    script
+}
+
+#[derive(Debug)]
+pub struct SlurmGroupMemberCommand {
+    allocation: Arc<SlurmGroupAllocation>,
+    member: usize,
+    shell_command: String,
+}
+impl JobExecutionCommand for SlurmGroupMemberCommand {
+    fn method(&self) -> ExecutionMethod {
+        ExecutionMethod::Slurm
+    }
+
+    fn shell_command(&self) -> String {
+        self.shell_command.clone()
+    }
+
+    // waits for the submission, so that the allocation does not escape the cancellation
+    fn slurm_allocation(&self) -> Option<SlurmJobID> {
+        self.allocation.job_id.outcome().ok()
+    }
+
+    // the job already runs as part of the allocation, so it is only followed from now on
+    fn spawn(
+        self: Box<Self>,
+        _grant: &ResourceGrant,
+    ) -> Result<Box<dyn JobExecutionChild>, JobExecutionError> {
+        Ok(Box::new(SlurmGroupMemberChild {
+            allocation: self.allocation,
+            job_id: None,
+            member: self.member,
+            exit_code: None,
+            skipped: false,
+            state: None,
+            allocation_state: None,
+            accounting: None,
+            cancellation: None,
+            finished: false,
+            warning: None,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct SlurmGroupMemberChild {
+    allocation: Arc<SlurmGroupAllocation>,
+    // the job ID of the allocation, which is followed once it was submitted
+    job_id: Option<SlurmJobID>,
+    member: usize,
+    exit_code: Option<i32>,
+    skipped: bool,
+    // the state of the allocation as of the last poll
+    state: Option<JobState>,
+    // only known if the allocation ended before the job finished
+    allocation_state: Option<JobState>,
+    accounting: Option<JobAccounting>,
+    // jobs which can never start are cancelled in the background before they count as finished
+    cancellation: Option<Background<()>>,
+    finished: bool,
+    warning: Option<String>,
+}
+// the allocation is polled for as long as any of its members is around
+impl Drop for SlurmGroupMemberChild {
+    fn drop(&mut self) {
+        if let Some(job_id) = self.job_id {
+            unfollow_slurm_job(job_id);
+        }
+    }
+}
+
+impl SlurmGroupMemberChild {
+    fn poll(&mut self) -> Result<bool, JobExecutionError> {
+        if self.job_id.is_none()
+            && let Some(job_id) = self.allocation.job_id.try_outcome().transpose()?
+        {
+            follow_slurm_job(job_id);
+            self.job_id = Some(job_id);
+        }
+        let Some(job_id) = self.job_id else {
+            return Ok(false);
+        };
+        if let Some(cancellation) = &self.cancellation {
+            return match cancellation.try_outcome() {
+                Some(result) => result.map(|()| true).map_err(JobExecutionError::from),
+                None => Ok(false),
+            };
+        }
+
+        if self.read_exit_code(job_id)? {
+            return Ok(true);
+        }
+
+        let Some(PolledJob {
+            state,
+            accounting,
+            accounting_unavailable,
+            warning,
+        }) = polled_job(job_id)?
+        else {
+            return Ok(false);
+        };
+        self.warning = self.warning.take().or(warning);
+        if let Some(state) = state.as_ref().filter(|state| !state.is_finished()) {
+            self.state = Some(state.clone());
+            // the job fails with the pending state of the allocation as its final state
+            if state.unresolvable_pending_reason().is_some() {
+                self.allocation_state = Some(state.clone());
+                self.cancellation = Some(Background::spawn(move || cancel_slurm_jobs(&[job_id])));
+            }
+            return Ok(false);
+        }
+
+        // the job might have finished right before the allocation
+        if self.read_exit_code(job_id)? {
+            return Ok(true);
+        }
+
+        self.accounting = accounting;
+        let accounted_state = self.accounting.as_ref().map(|accounting| &accounting.state);
+        self.allocation_state = match (accounted_state, state) {
+            (Some(accounted), _) if accounted.is_finished() => Some(accounted.clone()),
+            (_, Some(state)) => Some(state),
+            (Some(_), None) => return Ok(false),
+            // without accounting, it remains unknown why the allocation ended before the job
+            // finished
+            (None, None) if accounting_unavailable => Some(
+                self.state
+                    .clone()
+                    .filter(JobState::is_finished)
+                    .unwrap_or(JobState::Completed),
+            ),
+            (None, None) => return Err(SlurmError::JobUnknown(job_id).into()),
+        };
+
+        Ok(true)
+    }
+
+    fn read_exit_code(&mut self, job_id: SlurmJobID) -> Result<bool, SlurmError> {
+        let path = SlurmGroupAllocation::exit_code_path(job_id, self.member);
+        let exit_code = match std::fs::read_to_string(&path) {
+            Ok(exit_code) => exit_code,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(SlurmError::GroupExitCodeRead(path, err.into())),
+        };
+
+        if exit_code.trim() == SKIPPED_MARKER {
+            self.skipped = true;
+            return Ok(true);
+        }
+        self.exit_code = Some(exit_code.trim().parse().map_err(|err| {
+            SlurmError::GroupExitCodeParsing(path, format!("`{exit_code}` is no exit code\n{err}"))
+        })?);
+        Ok(true)
+    }
+}
+impl JobExecutionChild for SlurmGroupMemberChild {
+    fn exit_notification(&self) -> ExitNotification<'_> {
+        ExitNotification::Polling(slurm_poll_interval())
+    }
+
+    // the outcome is final once the job finished, so that e.g. a job which was cancelled since
+    // it would never leave the queue is not polled (and cancelled) again
+    fn try_wait(&mut self) -> Result<bool, JobExecutionError> {
+        if !self.finished {
+            self.finished = self.poll()?;
+        }
+        Ok(self.finished)
+    }
+
+    fn wait(&mut self) -> Result<(), JobExecutionError> {
+        while !self.try_wait()? {
+            thread::sleep(slurm_poll_interval());
+        }
+
+        let job_id = self.job_id.expect("finished jobs were submitted");
+        if self.skipped {
+            return Err(SlurmError::GroupMemberSkipped { job_id }.into());
+        }
+        match (self.exit_code, &self.allocation_state) {
+            (Some(0), _) => Ok(()),
+            (Some(code), _) => Err(SlurmError::GroupMemberFailure { job_id, code }.into()),
+            (None, state) => Err(SlurmError::JobFailure {
+                job_id,
+                state: state
+                    .clone()
+                    .expect("either the exit code or the allocation state is known"),
+                accounting: self.accounting.clone(),
+            }
+            .into()),
+        }
+    }
+
+    // the other jobs of the group are cancelled as well, while an allocation whose submission
+    // failed has nothing left to cancel
+    fn kill(&mut self) -> Result<(), JobExecutionError> {
+        match self.allocation.job_id.outcome() {
+            Ok(job_id) => Ok(cancel_slurm_jobs(&[job_id])?),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn status(&self) -> Option<String> {
+        pending_status(self.state.as_ref()?, self.allocation.submission_time)
+    }
+
+    fn take_warning(&mut self) -> Option<String> {
+        self.warning.take().or_else(take_slurm_warning)
+    }
+
+    // the submission error is reported once the job is polled
+    fn await_submission(&mut self) {
+        let _ = self.allocation.job_id.outcome();
+    }
+
+    fn record(&self) -> Option<ExecutionRecord> {
+        Some(ExecutionRecord::Slurm {
+            job_id: self.allocation.job_id.try_outcome()?.ok()?,
+            previous_attempts: Vec::new(),
+        })
+    }
+}
@@ -1,11 +1,21 @@
+use accounting::{JobAccounting, query_job_accounting};
 use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
-use options::{FormatSlurmTime, MemorySize, SlurmExecutionOptions};
+use derive_more::Display;
+use indicatif::HumanDuration;
+use options::{FormatSlurmTime, SlurmExecutionOptions};
+use polling::{PolledJob, follow_slurm_job, polled_job, slurm_poll_interval, unfollow_slurm_job};
+use retry::{Background, retry_transient};
+use scratch::{quote, scratch_shell_command};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _};
 use state::JobState;
 use std::{
+    collections::HashMap,
     io::Write,
     process::{Command, Stdio},
-    rc::Rc,
     str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Instant,
 };
 use tempfile::NamedTempFile;
 
@@ -13,17 +23,160 @@ use crate::{
     commands::{AsCommandError, CommandError, OutputUtf8},
     nix_environment::NixRunCommand,
     utils::IoError,
+    workflow::{
+        job::{
+            JobError,
+            resources::{ResourceGrant, THREADS_ENVIRONMENT_VARIABLE},
+        },
+        specification::StepInfo,
+    },
 };
 
-use super::{ExecutionError, JobExecutionChild, JobExecutionCommand, JobExecutionError};
+use super::{
+    ExecutionError, ExecutionMethod, ExecutionRecord, ExitNotification, JobExecutionChild,
+    JobExecutionCommand, JobExecutionError,
+};
 
+mod accounting;
+mod group;
 pub(super) mod options;
+mod polling;
+mod retry;
+mod scratch;
 mod state;
 
-pub type SlurmJobID = u64;
+pub use group::submit_slurm_group;
+pub use polling::set_slurm_poll_interval;
+pub use retry::set_slurm_grace_period;
 
-#[derive(Debug)]
-pub(super) struct SlurmExecutionCommand {
+// jobs whose command redirects its output to the log of its step (e.g. tasks of job arrays) have
+// the output of slurm itself (e.g. that the job was cancelled due to its time limit) written here
+const SLURM_OUTPUT_DIRECTORY: &str = ".nixflow/slurm";
+
+// the directory has to exist before the job starts, since slurm does not create it
+fn slurm_output_option(file_name: &str) -> Result<String, SlurmError> {
+    let directory = PathBuf::from(SLURM_OUTPUT_DIRECTORY);
+    std::fs::create_dir_all(&directory)
+        .map_err(|err| SlurmError::OutputDirectoryCreation(directory.clone(), err.into()))?;
+    return Ok(format!("--output={}", directory.join(file_name)));
+}
+
+// sbatch and squeue print warnings e.g. about discouraged options or from job submit plugins of the
+// site, which are shown through whichever job asks for warnings next
+static SLURM_WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn warn_about_stderr(command: &Command, stderr: &str) {
+    if stderr.trim().is_empty() {
+        return;
+    }
+    SLURM_WARNINGS
+        .lock()
+        .expect("warnings lock is never poisoned")
+        .push(format!("`{command:?}` printed\n{}", stderr.trim()));
+}
+
+pub fn take_slurm_warning() -> Option<String> {
+    let warnings = std::mem::take(
+        &mut *SLURM_WARNINGS
+            .lock()
+            .expect("warnings lock is never poisoned"),
+    );
+    return (!warnings.is_empty()).then(|| warnings.join("\n"));
+}
+
+// array tasks are addressed as `<job id>_<task index>` by all slurm commands, while plain job IDs
+// are kept as numbers in the records for compatibility
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Display)]
+#[display("{job}{}", array_task.map(|task| format!("_{task}")).unwrap_or_default())]
+pub struct SlurmJobID {
+    job: u64,
+    array_task: Option<u32>,
+}
+impl SlurmJobID {
+    fn array_task(array_job: SlurmJobID, task: u32) -> Self {
+        Self {
+            job: array_job.job,
+            array_task: Some(task),
+        }
+    }
+
+    // array tasks which did not start yet may be listed compressed into a single line (e.g.
+    // `123_[0-3,5%2]`, where the suffix after `%` limits how many tasks run at once)
+    fn expand(s: &str) -> Result<Vec<Self>, String> {
+        let Some((job, tasks)) = s.split_once("_[") else {
+            return Ok(vec![Self::from_str(s)?]);
+        };
+        let tasks = tasks
+            .strip_suffix(']')
+            .ok_or(format!("expected the array tasks of `{s}` to end with `]`"))?;
+        let tasks = tasks.split_once('%').map_or(tasks, |(tasks, _)| tasks);
+        let array_job = Self::from_str(job)?;
+
+        let parse_task = |task: &str| {
+            task.parse::<u32>()
+                .map_err(|err| format!("failed to parse `{task}` as an array task\n{err}"))
+        };
+        let mut job_ids = Vec::new();
+        for range in tasks.split(',') {
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (parse_task(first)?, parse_task(last)?),
+                None => (parse_task(range)?, parse_task(range)?),
+            };
+            job_ids.extend((first..=last).map(|task| Self::array_task(array_job, task)));
+        }
+        Ok(job_ids)
+    }
+}
+impl FromStr for SlurmJobID {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (job, array_task) = match s.split_once('_') {
+            Some((job, array_task)) => (job, Some(array_task)),
+            None => (s, None),
+        };
+
+        Ok(Self {
+            job: job
+                .parse()
+                .map_err(|err| format!("failed to parse `{job}` as a job ID\n{err}"))?,
+            array_task: array_task
+                .map(|task| {
+                    task.parse()
+                        .map_err(|err| format!("failed to parse `{task}` as an array task\n{err}"))
+                })
+                .transpose()?,
+        })
+    }
+}
+impl Serialize for SlurmJobID {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.array_task {
+            None => serializer.serialize_u64(self.job),
+            Some(_) => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+impl<'de> Deserialize<'de> for SlurmJobID {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RecordedJobID {
+            Job(u64),
+            ArrayTask(String),
+        }
+
+        match RecordedJobID::deserialize(deserializer)? {
+            RecordedJobID::Job(job) => Ok(Self {
+                job,
+                array_task: None,
+            }),
+            RecordedJobID::ArrayTask(id) => Self::from_str(&id).map_err(D::Error::custom),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SlurmExecutionCommand {
     shell_command: String,
     log: PathBuf,
     options: SlurmExecutionOptions,
@@ -31,34 +184,336 @@ pub(super) struct SlurmExecutionCommand {
 impl SlurmExecutionCommand {
     pub fn new(
         target: &Box<dyn NixRunCommand>,
-        log: PathBuf,
+        step: &StepInfo,
         options: SlurmExecutionOptions,
-    ) -> Self {
-        Self {
-            shell_command: target.shell_command(),
-            log,
+    ) -> Result<Self, JobError> {
+        let shell_command = match options.scratch {
+            true => scratch_shell_command(&target.shell_command(), &step.inputs, &step.outputs)?,
+            false => target.shell_command(),
+        };
+
+        Ok(Self {
+            shell_command,
+            log: step.log.clone(),
             options,
-        }
+        })
+    }
+
+    pub fn shares_allocation_with(&self, other: &SlurmExecutionCommand) -> bool {
+        self.options.shares_allocation_with(&other.options)
     }
 }
 
 impl JobExecutionCommand for SlurmExecutionCommand {
-    fn spawn(self: Box<Self>) -> Result<Box<dyn JobExecutionChild>, JobExecutionError> {
-        let jd = slurm_execute(self.shell_command, &self.log, &self.options)?;
+    fn method(&self) -> ExecutionMethod {
+        ExecutionMethod::Slurm
+    }
+
+    fn slurm_command(&self) -> Option<&SlurmExecutionCommand> {
+        Some(self)
+    }
 
-        Ok(Box::new(SlurmExecutionChild::new(job_id)))
+    fn shell_command(&self) -> String {
+        self.shell_command.clone()
     }
+
+    // the job is only submitted together with all other jobs started at the same time, see
+    // `submit_slurm_children`
+    fn spawn(
+        self: Box<Self>,
+        grant: &ResourceGrant,
+    ) -> Result<Box<dyn JobExecutionChild>, JobExecutionError> {
+        Ok(Box::new(SlurmExecutionChild::new(*self, grant.clone())))
+    }
+
+    // resubmission is up to the user, since nobody is waiting for the job to finish
+    fn submit(
+        &self,
+        grant: &ResourceGrant,
+        dependencies: &[SlurmJobID],
+    ) -> Result<SlurmJobID, JobError> {
+        slurm_execute(
+            &self.shell_command,
+            &self.log,
+            &self.options,
+            grant,
+            dependencies,
+        )
+        .map_err(|err| JobError::Submission(err.into()))
+    }
+}
+
+// a previous attempt of a job, which was resubmitted with more resources
+#[derive(Clone, Debug, Display, Deserialize, Serialize)]
+#[display("job {job_id} ({state}, log `{log}`)")]
+pub struct SlurmAttempt {
+    pub job_id: SlurmJobID,
+    pub state: String,
+    pub log: PathBuf,
+}
+
+// sbatch runs in the background, since it is retried for as long as it fails transiently
+#[derive(Debug)]
+enum SlurmSubmission {
+    // the job waits to be submitted together with the other jobs started at the same time
+    Unsubmitted,
+    // the job IDs are those of all jobs which were submitted together (e.g. as a job array)
+    Pending {
+        job_ids: Background<Vec<SlurmJobID>>,
+        index: usize,
+    },
+    Submitted(SlurmJobID),
+    Failed,
 }
 
 #[derive(Debug)]
 pub struct SlurmExecutionChild {
-    job_id: SlurmJobID,
+    submission: SlurmSubmission,
+    submission_time: Option<Instant>,
+    state: Option<JobState>,
+    accounting: Option<JobAccounting>,
+    // jobs which can never start are cancelled in the background before they count as finished
+    cancellation: Option<Background<()>>,
+    finished: bool,
+    warning: Option<String>,
+    command: SlurmExecutionCommand,
+    grant: ResourceGrant,
+    previous_attempts: Vec<SlurmAttempt>,
+}
+impl JobExecutionChild for SlurmExecutionChild {
+    fn exit_notification(&self) -> ExitNotification<'_> {
+        ExitNotification::Polling(slurm_poll_interval())
+    }
+
+    // the outcome is final once the job finished, so that e.g. a job which was cancelled since
+    // it would never leave the queue is not polled (and cancelled) again
+    fn try_wait(&mut self) -> Result<bool, JobExecutionError> {
+        if !self.finished && self.submitted(false)? {
+            self.finished = self.poll()?;
+        }
+        Ok(self.finished)
+    }
+
+    fn wait(&mut self) -> Result<(), JobExecutionError> {
+        while !self.try_wait()? {
+            thread::sleep(slurm_poll_interval());
+        }
+
+        match self.state.as_ref().expect("state was polled at least once") {
+            JobState::Completed => Ok(()),
+            state => Err(SlurmError::JobFailure {
+                job_id: self.job_id(),
+                state: state.clone(),
+                accounting: self.accounting.clone(),
+            }
+            .into()),
+        }
+    }
+
+    // a submission in flight is waited for, so that the job does not escape the cancellation,
+    // while a job whose submission failed has nothing left to cancel
+    fn kill(&mut self) -> Result<(), JobExecutionError> {
+        if !self.submitted(true).unwrap_or(false) {
+            return Ok(());
+        }
+        Ok(cancel_slurm_jobs(&[self.job_id()])?)
+    }
+
+    fn status(&self) -> Option<String> {
+        pending_status(self.state.as_ref()?, self.submission_time?)
+    }
+
+    fn take_warning(&mut self) -> Option<String> {
+        self.warning.take().or_else(take_slurm_warning)
+    }
+
+    // the submission error is reported once the job is polled
+    fn await_submission(&mut self) {
+        let _ = self.submitted(true);
+    }
+
+    fn unsubmitted_slurm_child(&mut self) -> Option<&mut SlurmExecutionChild> {
+        matches!(self.submission, SlurmSubmission::Unsubmitted).then_some(self)
+    }
+
+    // until a resubmission succeeded, the previous attempt counts as the last one
+    fn record(&self) -> Option<ExecutionRecord> {
+        let (job_id, previous_attempts) = match self.submission {
+            SlurmSubmission::Submitted(job_id) => (job_id, self.previous_attempts.as_slice()),
+            _ => {
+                let (last_attempt, previous_attempts) = self.previous_attempts.split_last()?;
+                (last_attempt.job_id, previous_attempts)
+            }
+        };
+        Some(ExecutionRecord::Slurm {
+            job_id,
+            previous_attempts: previous_attempts.to_vec(),
+        })
+    }
+}
+
+// the job is polled for as long as the child is around
+impl Drop for SlurmExecutionChild {
+    fn drop(&mut self) {
+        if let SlurmSubmission::Submitted(job_id) = self.submission {
+            unfollow_slurm_job(job_id);
+        }
+    }
 }
-impl JobExecutionChild for SlurmExecutionChild {}
 
 impl SlurmExecutionChild {
-    pub fn new(job_id: SlurmJobID) -> Self {
-        SlurmExecutionChild { job_id }
+    // squeue only lists jobs for a short while after they finished and does not know how they
+    // finished, which is why the final state is taken from the accounting
+    fn poll(&mut self) -> Result<bool, JobExecutionError> {
+        if let Some(cancellation) = &self.cancellation {
+            return match cancellation.try_outcome() {
+                Some(result) => result.map(|()| true).map_err(JobExecutionError::from),
+                None => Ok(false),
+            };
+        }
+
+        let job_id = self.job_id();
+        let Some(PolledJob {
+            state,
+            accounting,
+            accounting_unavailable,
+            warning,
+        }) = polled_job(job_id)?
+        else {
+            return Ok(false);
+        };
+        self.warning = self.warning.take().or(warning);
+        if let Some(state) = state.as_ref().filter(|state| !state.is_finished()) {
+            self.state = Some(state.clone());
+            // the job fails with the pending state as its final state
+            if state.unresolvable_pending_reason().is_some() {
+                self.cancellation = Some(Background::spawn(move || cancel_slurm_jobs(&[job_id])));
+            }
+            return Ok(false);
+        }
+
+        self.accounting = accounting;
+        let accounted_state = self.accounting.as_ref().map(|accounting| &accounting.state);
+        self.state = match (accounted_state, state) {
+            (Some(accounted), _) if accounted.is_finished() => Some(accounted.clone()),
+            // the accounting lags behind
+            (_, Some(state)) => Some(state),
+            (Some(_), None) => return Ok(false),
+            // without accounting, the exit code remains unknown and missing outputs are only
+            // noticed afterwards
+            (None, None) if accounting_unavailable => Some(
+                self.state
+                    .clone()
+                    .filter(JobState::is_finished)
+                    .unwrap_or(JobState::Completed),
+            ),
+            (None, None) => return Err(SlurmError::JobUnknown(job_id).into()),
+        };
+
+        if self.resubmit()? {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn new(command: SlurmExecutionCommand, grant: ResourceGrant) -> Self {
+        SlurmExecutionChild {
+            submission: SlurmSubmission::Unsubmitted,
+            submission_time: None,
+            state: None,
+            accounting: None,
+            cancellation: None,
+            finished: false,
+            warning: None,
+            command,
+            grant,
+            previous_attempts: Vec::new(),
+        }
+    }
+
+    fn job_id(&self) -> SlurmJobID {
+        let SlurmSubmission::Submitted(job_id) = self.submission else {
+            unreachable!("only submitted jobs are polled or cancelled")
+        };
+        job_id
+    }
+
+    // whether the job was submitted by now, a submission in flight is only waited for if asked to
+    fn submitted(&mut self, wait: bool) -> Result<bool, SlurmError> {
+        let (job_ids, index) = match &self.submission {
+            SlurmSubmission::Unsubmitted | SlurmSubmission::Failed => return Ok(false),
+            SlurmSubmission::Submitted(_) => return Ok(true),
+            SlurmSubmission::Pending { job_ids, index } => match wait {
+                true => (job_ids.outcome(), *index),
+                false => match job_ids.try_outcome() {
+                    Some(outcome) => (outcome, *index),
+                    None => return Ok(false),
+                },
+            },
+        };
+
+        let job_id = match job_ids {
+            Ok(job_ids) => job_ids[index],
+            Err(err) => {
+                self.submission = SlurmSubmission::Failed;
+                return Err(err);
+            }
+        };
+        follow_slurm_job(job_id);
+        self.submission = SlurmSubmission::Submitted(job_id);
+        self.submission_time = Some(Instant::now());
+        Ok(true)
+    }
+
+    // jobs which ran out of memory or time are resubmitted with more of the respective resource,
+    // while the log of every previous attempt is kept next to the log of the current one
+    fn resubmit(&mut self) -> Result<bool, SlurmError> {
+        let state = self.state.as_ref().expect("state was polled at least once");
+        let out_of_memory = match state {
+            JobState::OutOfMemory => true,
+            JobState::Timeout => false,
+            _ => return Ok(false),
+        };
+
+        let attempt = self.previous_attempts.len() + 1;
+        if attempt >= self.command.options.retry.max_attempts as usize {
+            return Ok(false);
+        }
+        let Some(options) = self.command.options.escalated(out_of_memory) else {
+            return Ok(false);
+        };
+
+        let attempt_log = PathBuf::from(format!("{}.attempt{attempt}", self.command.log));
+        std::fs::rename(&self.command.log, &attempt_log)
+            .map_err(|err| SlurmError::AttemptLogRetention(attempt_log.clone(), err.into()))?;
+
+        self.previous_attempts.push(SlurmAttempt {
+            job_id: self.job_id(),
+            state: state.to_string(),
+            log: attempt_log,
+        });
+        unfollow_slurm_job(self.job_id());
+        self.command.options = options;
+        let (command, grant) = (self.command.clone(), self.grant.clone());
+        self.submission = SlurmSubmission::Pending {
+            job_ids: Background::spawn(move || {
+                slurm_execute(
+                    &command.shell_command,
+                    &command.log,
+                    &command.options,
+                    &grant,
+                    &[],
+                )
+                .map(|job_id| vec![job_id])
+            }),
+            index: 0,
+        };
+        self.submission_time = None;
+        self.state = None;
+        self.accounting = None;
+
+        Ok(true)
     }
 }
 
@@ -81,19 +536,169 @@ pub enum SlurmError {
 
     #[error("failed to read the slurm job ID from the output of `{command}`\n{error}")]
     JobExecutionReadJobID { command: String, error: String },
+
+    #[error("slurm job {job_id} did not complete successfully, final state: {state}")]
+    JobFailure {
+        job_id: SlurmJobID,
+        state: JobState,
+        accounting: Option<JobAccounting>,
+    },
+
+    #[error("slurm job {0} is known neither to squeue nor to sacct")]
+    JobUnknown(SlurmJobID),
+
+    #[error("failed to query the slurm accounting\n{0}")]
+    JobAccountingQuery(CommandError),
+
+    #[error("failed to parse the accounting output from `{command}`\n{error}")]
+    JobAccountingParsing { command: String, error: String },
+
+    #[error("failed to cancel the slurm jobs\n{0}")]
+    JobCancellation(CommandError),
+
+    #[error("failed to keep the log of the previous attempt as `{0}`\n{1}")]
+    AttemptLogRetention(PathBuf, IoError),
+
+    #[error("failed to create the directory `{0}` for the output of slurm\n{1}")]
+    OutputDirectoryCreation(PathBuf, IoError),
+
+    #[error("failed to read the exit code of a job of the group from `{0}`\n{1}")]
+    GroupExitCodeRead(PathBuf, IoError),
+
+    #[error("failed to parse the exit code of a job of the group from `{0}`\n{1}")]
+    GroupExitCodeParsing(PathBuf, String),
+
+    #[error("job of the slurm group allocation {job_id} exited with non-zero exit code {code}")]
+    GroupMemberFailure { job_id: SlurmJobID, code: i32 },
+
+    #[error("job of the slurm group allocation {job_id} was skipped, since a parent job failed")]
+    GroupMemberSkipped { job_id: SlurmJobID },
+}
+impl ExecutionError for SlurmError {
+    fn job_error(&self) -> Option<JobError> {
+        if let Self::GroupMemberFailure { job_id, code } = self {
+            return Some(JobError::SlurmGroupMemberFailure {
+                job_id: *job_id,
+                code: *code,
+            });
+        }
+        if let Self::GroupMemberSkipped { job_id } = self {
+            return Some(JobError::SlurmGroupParentFailure { job_id: *job_id });
+        }
+
+        let Self::JobFailure {
+            job_id,
+            state,
+            accounting,
+        } = self
+        else {
+            return None;
+        };
+
+        let job_id = *job_id;
+        let unknown = || "unknown".to_owned();
+        Some(match (state, accounting) {
+            (JobState::Timeout, accounting) => JobError::SlurmTimeout {
+                job_id,
+                elapsed: accounting
+                    .as_ref()
+                    .map(|accounting| accounting.elapsed.clone())
+                    .unwrap_or_else(unknown),
+            },
+            (JobState::OutOfMemory, accounting) => JobError::SlurmOutOfMemory {
+                job_id,
+                max_rss: accounting
+                    .as_ref()
+                    .and_then(|accounting| accounting.max_rss.clone())
+                    .unwrap_or_else(unknown),
+            },
+            (JobState::NodeFail, accounting) => JobError::SlurmNodeFailure {
+                job_id,
+                nodes: accounting
+                    .as_ref()
+                    .map(|accounting| accounting.node_list.clone())
+                    .unwrap_or_else(unknown),
+            },
+            (
+                JobState::Pending {
+                    reason: Some(reason),
+                },
+                _,
+            ) => JobError::SlurmUnresolvablePending {
+                job_id,
+                reason: reason.to_string(),
+            },
+            (JobState::Cancelled, _) => JobError::SlurmCancellation { job_id },
+            (JobState::Preempted, _) => JobError::SlurmPreemption { job_id },
+            (JobState::Failed, Some(accounting)) if accounting.signal != 0 => {
+                JobError::SlurmSignalTermination {
+                    job_id,
+                    signal: accounting.signal,
+                }
+            }
+            (JobState::Failed, Some(accounting)) => JobError::SlurmNonZeroExitCode {
+                job_id,
+                code: accounting.exit_code,
+                nodes: accounting.node_list.clone(),
+            },
+            _ => return None,
+        })
+    }
 }
-impl ExecutionError for SlurmError {}
 impl From<SlurmError> for JobExecutionError {
     fn from(error: SlurmError) -> Self {
-        JobExecutionError(Rc::new(error))
+        JobExecutionError(Arc::new(error))
     }
 }
 
 pub fn slurm_execute(
-    shell_command: String,
+    shell_command: &str,
     log: &Path,
     options: &SlurmExecutionOptions,
+    grant: &ResourceGrant,
+    dependencies: &[SlurmJobID],
 ) -> Result<SlurmJobID, SlurmError> {
+    let mut command = sbatch_command(options, dependencies);
+    command.arg(format!("--output={log}"));
+
+    submit_execution_script(
+        command,
+        &format!(
+            "#!/bin/sh\nexport {THREADS_ENVIRONMENT_VARIABLE}={threads}\n{shell_command}",
+            threads = grant.threads
+        ),
+    )
+}
+
+// every task of the array looks up its own command and log in a table, which is why the logs are
+// redirected by the script itself
+fn slurm_execute_array(
+    commands: &[SlurmExecutionCommand],
+    options: &SlurmExecutionOptions,
+    grant: &ResourceGrant,
+) -> Result<SlurmJobID, SlurmError> {
+    let mut command = sbatch_command(options, &[]);
+    command
+        .arg(format!("--array=0-{}", commands.len() - 1))
+        .arg(slurm_output_option("%A_%a.log")?);
+
+    let mut script = format!(
+        "#!/bin/sh\nexport {THREADS_ENVIRONMENT_VARIABLE}={threads}\ncase \"$SLURM_ARRAY_TASK_ID\" in\n",
+        threads = grant.threads
+    );
+    for (task, task_command) in commands.iter().enumerate() {
+        script += &format!(
+            "    {task})\n        exec >{log} 2>&1\n        {shell_command}\n        ;;\n",
+            log = quote(&task_command.log),
+            shell_command = task_command.shell_command
+        );
+    }
+    script += "esac\n";
+
+    submit_execution_script(command, &script)
+}
+
+fn sbatch_command(options: &SlurmExecutionOptions, dependencies: &[SlurmJobID]) -> Command {
     let mut command = Command::new("sbatch");
     command.arg("--account").arg(&options.account);
 
@@ -112,82 +717,544 @@ pub fn slurm_execute(
     command
         .arg("--time")
         .arg(options.runtime.format_slurm_time())
-        .arg(match options.memory_size {
-            MemorySize::AllAvailable => "--mem=0".to_owned(),
-            MemorySize::Fixed((size, unit)) => {
-                format!("--mem={size}{unit}", unit = unit.as_slurm_suffix())
-            }
-        })
         .arg("--cpus-per-task")
-        .arg(options.cpu_count.to_string())
-        .arg("--gpus")
-        .arg(options.gpu_count.to_string());
+        .arg(options.cpu_count.to_string());
+
+    if let Some(memory_size) = options.memory_size {
+        command.arg(format!("--mem={}", memory_size.as_slurm_size()));
+    }
+
+    if let Some(memory_per_cpu) = options.memory_per_cpu {
+        command.arg(format!("--mem-per-cpu={}", memory_per_cpu.as_slurm_size()));
+    }
+
+    if options.gpu_count > 0 {
+        command.arg("--gpus").arg(options.gpu_count.to_string());
+    }
+
+    if let Some(node_count) = options.node_count {
+        command.arg("--nodes").arg(node_count.to_string());
+    }
+
+    if let Some(task_count) = options.task_count {
+        command.arg("--ntasks").arg(task_count.to_string());
+    }
+
+    if let Some(tasks_per_node) = options.tasks_per_node {
+        command
+            .arg("--ntasks-per-node")
+            .arg(tasks_per_node.to_string());
+    }
+
+    if !options.generic_resources.is_empty() {
+        command
+            .arg("--gres")
+            .arg(join_displayed(&options.generic_resources));
+    }
+
+    if options.exclusive {
+        command.arg("--exclusive");
+    }
+
+    if let Some(reservation) = &options.reservation {
+        command.arg("--reservation").arg(reservation);
+    }
+
+    if let Some(excluded_nodes) = &options.excluded_nodes {
+        command.arg("--exclude").arg(excluded_nodes.join(","));
+    }
+
+    if let Some(node_list) = &options.node_list {
+        command.arg("--nodelist").arg(node_list.join(","));
+    }
+
+    if !options.licenses.is_empty() {
+        command
+            .arg("--licenses")
+            .arg(join_displayed(&options.licenses));
+    }
+
+    if let Some(begin) = &options.begin {
+        command.arg("--begin").arg(begin.to_string());
+    }
+
+    if !options.mail_types.is_empty() {
+        command
+            .arg("--mail-type")
+            .arg(join_displayed(&options.mail_types));
+    }
 
+    if let Some(mail_user) = &options.mail_user {
+        command.arg("--mail-user").arg(mail_user);
+    }
+
+    if let Some(job_name) = &options.job_name {
+        command.arg("--job-name").arg(job_name);
+    }
+
+    if let Some(comment) = &options.comment {
+        command.arg("--comment").arg(comment);
+    }
+
+    command.args(
+        options
+            .extra_args
+            .iter()
+            .map(|argument| argument.to_string()),
+    );
+
+    // jobs whose dependencies failed would wait forever otherwise
+    if !dependencies.is_empty() {
+        command
+            .arg(format!(
+                "--dependency=afterok:{}",
+                dependencies
+                    .iter()
+                    .map(|job_id| job_id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(":")
+            ))
+            .arg("--kill-on-invalid-dep=yes");
+    }
+
+    command
+}
+
+fn join_displayed<T: std::fmt::Display>(items: &[T]) -> String {
+    return items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+}
+
+fn submit_execution_script(mut command: Command, script: &str) -> Result<SlurmJobID, SlurmError> {
     let mut execution_script =
         NamedTempFile::new().map_err(|err| SlurmError::JobExecutionScriptCreation(err.into()))?;
-    write!(execution_script, "#!/bin/sh\n{shell_command}")
+    write!(execution_script, "{script}")
         .map_err(|err| SlurmError::JobExecutionScriptWrite(err.into()))?;
+    // sbatch copies the script on submission, so it only has to live until then
+    let execution_script_path = execution_script.into_temp_path();
 
-    command
-        .arg(format!("--output={log}"))
-        .arg(execution_script.into_temp_path());
+    command.arg(&execution_script_path);
+
+    // the job might have been submitted despite a failure (e.g. a timeout while waiting for the
+    // reply of the controller), which is why it is only submitted again if it is not queued
+    let mut attempted = false;
+    retry_transient(|| {
+        if std::mem::replace(&mut attempted, true)
+            && let Some(job_id) = find_submitted_script(&execution_script_path)?
+        {
+            return Ok(job_id);
+        }
+
+        let output: OutputUtf8 = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|err| CommandError::new_io(&command, err))
+            .map_err(SlurmError::JobExecute)?
+            .into();
+
+        output
+            .status
+            .as_piped_command_result(&command, &output.stdout, &output.stderr)
+            .map_err(SlurmError::JobExecute)?;
+        warn_about_stderr(&command, &output.stderr);
+        read_submitted_job_id(&command, &output.stdout)
+    })
+}
+
+fn read_submitted_job_id(command: &Command, output: &str) -> Result<SlurmJobID, SlurmError> {
+    let job_id = output
+        .trim()
+        .strip_prefix("Submitted batch job ")
+        .ok_or("expected output to start with `Submitted batch job `".to_owned())
+        .map_err(|error| SlurmError::JobExecutionReadJobID {
+            command: format!("{command:?}"),
+            error,
+        })?;
+
+    SlurmJobID::from_str(job_id).map_err(|err| SlurmError::JobExecutionReadJobID {
+        command: format!("{command:?}"),
+        error: format!("failed to parse string after `Submitted batch job `\n{err}"),
+    })
+}
+
+// every submission has a temporary script of its own, whose path squeue lists as the command of
+// the job
+fn find_submitted_script(script: &std::path::Path) -> Result<Option<SlurmJobID>, SlurmError> {
+    let mut command = Command::new("squeue");
+    command.arg("--me").arg("--noheader").arg("--format=%F %o");
 
     let output: OutputUtf8 = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .output()
         .map_err(|err| CommandError::new_io(&command, err))
-        .map_err(|err| SlurmError::JobExecute(err))?
+        .map_err(SlurmError::JobStatePoll)?
         .into();
 
     output
         .status
         .as_piped_command_result(&command, &output.stdout, &output.stderr)
-        .map_err(|err| SlurmError::JobStatePoll(err))?;
-    assert!(output.stderr.trim().is_empty());
+        .map_err(SlurmError::JobStatePoll)?;
 
-    let output = output.stdout.trim();
-    let job_id = output
-        .strip_prefix(output)
-        .ok_or("expected output to start with `Submitted batch job `".to_owned())
-        .map_err(|error| SlurmError::JobExecutionReadJobID {
-            command: format!("{command:?}"),
-            error,
-        })?;
+    output
+        .stdout
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .find(|(_, job_script)| std::path::Path::new(job_script) == script)
+        .map(|(job_id, _)| {
+            SlurmJobID::from_str(job_id).map_err(|error| SlurmError::JobStateParsing {
+                command: format!("{command:?}"),
+                error,
+            })
+        })
+        .transpose()
+}
 
-    Ok(
-        SlurmJobID::from_str(job_id).map_err(|err| SlurmError::JobExecutionReadJobID {
-            command: format!("{command:?}"),
-            error: format!(
-                "failed to parse string after `Submitted batch job ` as an integer\n{err}"
-            ),
-        })?,
-    )
+// children with the same options are submitted together as a job array, which puts much less
+// load on the slurm controller than submitting every job on its own; the submission fails the
+// children only once they are polled
+pub fn submit_slurm_children(mut children: Vec<&mut SlurmExecutionChild>) {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (index, child) in children.iter().enumerate() {
+        let group = groups.iter_mut().find(|group| {
+            let member = &children[group[0]];
+            member.command.options == child.command.options
+                && member.grant.threads == child.grant.threads
+        });
+        match group {
+            Some(group) => group.push(index),
+            None => groups.push(vec![index]),
+        }
+    }
+
+    for group in groups {
+        let commands = group
+            .iter()
+            .map(|index| children[*index].command.clone())
+            .collect::<Vec<_>>();
+        let grant = children[group[0]].grant.clone();
+        let job_ids = Background::spawn(move || match commands.as_slice() {
+            [command] => slurm_execute(
+                &command.shell_command,
+                &command.log,
+                &command.options,
+                &grant,
+                &[],
+            )
+            .map(|job_id| vec![job_id]),
+            _ => slurm_execute_array(&commands, &commands[0].options, &grant).map(|array_job| {
+                (0..commands.len() as u32)
+                    .map(|task| SlurmJobID::array_task(array_job, task))
+                    .collect()
+            }),
+        });
+
+        for (task, index) in group.into_iter().enumerate() {
+            children[index].submission = SlurmSubmission::Pending {
+                job_ids: job_ids.clone(),
+                index: task,
+            };
+        }
+    }
+}
+
+// the outcome of a job which was submitted by an earlier invocation
+#[derive(Clone, Debug, Display)]
+pub enum SubmittedJobOutcome {
+    #[display("{_0}")]
+    Unfinished(String),
+    // e.g. because slurm already forgot about the job
+    #[display("unknown to slurm")]
+    Unknown,
+    #[display("completed")]
+    Completed,
+    #[display("failed: {_0}")]
+    Failed(JobError),
+}
+
+pub fn query_submitted_job(job_id: SlurmJobID) -> Result<SubmittedJobOutcome, JobExecutionError> {
+    if let Some(state) = poll_job_state(job_id)?.filter(|state| !state.is_finished()) {
+        return Ok(SubmittedJobOutcome::Unfinished(state.to_string()));
+    }
+
+    let Some(accounting) = query_job_accounting(job_id)? else {
+        return Ok(SubmittedJobOutcome::Unknown);
+    };
+    Ok(match &accounting.state {
+        JobState::Completed => SubmittedJobOutcome::Completed,
+        state if !state.is_finished() => SubmittedJobOutcome::Unfinished(state.to_string()),
+        state => {
+            let error = SlurmError::JobFailure {
+                job_id,
+                state: state.clone(),
+                accounting: Some(accounting.clone()),
+            };
+            SubmittedJobOutcome::Failed(
+                error
+                    .job_error()
+                    .unwrap_or_else(|| JobError::JobExecution(error.into())),
+            )
+        }
+    })
 }
 
-pub fn poll_job_state(job_id: SlurmJobID) -> Result<JobState, SlurmError> {
+// describes why the job did not start yet and for how long it has been waiting already
+fn pending_status(state: &JobState, submission_time: Instant) -> Option<String> {
+    let JobState::Pending { reason } = state else {
+        return None;
+    };
+
+    let queued = HumanDuration(submission_time.elapsed());
+    return Some(match reason {
+        Some(reason) => format!("waiting: {reason}, queued for {queued}"),
+        None => format!("waiting, queued for {queued}"),
+    });
+}
+
+// returns nothing if squeue does not list the job (anymore)
+pub fn poll_job_state(job_id: SlurmJobID) -> Result<Option<JobState>, SlurmError> {
+    let states = retry_transient(|| poll_job_states(&[job_id]))?;
+    return listed_state(&states, job_id).cloned().transpose();
+}
+
+type ListedStates = HashMap<SlurmJobID, Result<JobState, SlurmError>>;
+
+// jobs which squeue does not list (anymore) are left out, array tasks are listed one by one; a
+// line which cannot be parsed only fails the jobs it belongs to
+pub fn poll_job_states(job_ids: &[SlurmJobID]) -> Result<ListedStates, SlurmError> {
     let mut command = Command::new("squeue");
     command
-        .arg("--job")
-        .arg(format!("{job_id}"))
-        .arg("--format=%i %T %r");
+        .arg("--jobs")
+        .arg(
+            job_ids
+                .iter()
+                .map(|job_id| job_id.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .arg("--array")
+        .arg("--noheader")
+        .arg("--format=%i %t %r");
 
     let output: OutputUtf8 = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .output()
         .map_err(|err| CommandError::new_io(&command, err))
-        .map_err(|err| SlurmError::JobStatePoll(err))?
+        .map_err(SlurmError::JobStatePoll)?
         .into();
 
+    if !output.status.success() && output.stderr.contains("Invalid job id") {
+        return Ok(HashMap::new());
+    }
     output
         .status
         .as_piped_command_result(&command, &output.stdout, &output.stderr)
-        .map_err(|err| SlurmError::JobStatePoll(err))?;
-    assert!(output.stderr.trim().is_empty());
+        .map_err(SlurmError::JobStatePoll)?;
+    warn_about_stderr(&command, &output.stderr);
 
-    JobState::from_polling_output(&output.stdout).map_err(|error| SlurmError::JobStateParsing {
-        command: format!("{command:?}"),
-        error,
+    let states = parse_job_states(&output.stdout)
+        .into_iter()
+        .map(|(job_id, state)| {
+            let state = state.map_err(|error| SlurmError::JobStateParsing {
+                command: format!("{command:?}"),
+                error,
+            });
+            (job_id, state)
+        })
+        .collect();
+    Ok(states)
+}
+
+// lines whose array tasks cannot be read are listed under the array job, which is why they apply
+// to all of its tasks
+fn listed_state(
+    states: &ListedStates,
+    job_id: SlurmJobID,
+) -> Option<&Result<JobState, SlurmError>> {
+    let array_job = SlurmJobID {
+        job: job_id.job,
+        array_task: None,
+    };
+    return states
+        .get(&job_id)
+        .or_else(|| states.get(&array_job).filter(|state| state.is_err()));
+}
+
+// lines without a readable job ID cannot be attributed to any job and are skipped
+fn parse_job_states(output: &str) -> HashMap<SlurmJobID, Result<JobState, String>> {
+    let mut states = HashMap::new();
+    for line in output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (job_id, state) = line.split_once(' ').unwrap_or((line, ""));
+        let state = JobState::from_polling_output(state);
+        match SlurmJobID::expand(job_id) {
+            Ok(job_ids) => {
+                for job_id in job_ids {
+                    states.insert(job_id, state.clone());
+                }
+            }
+            Err(err) => {
+                let Ok(job) = job_id.split('_').next().unwrap_or_default().parse() else {
+                    continue;
+                };
+                let job_id = SlurmJobID {
+                    job,
+                    array_task: None,
+                };
+                states.insert(job_id, Err(format!("{err}\nin line `{line}`")));
+            }
+        }
+    }
+    return states;
+}
+
+pub fn cancel_slurm_jobs(job_ids: &[SlurmJobID]) -> Result<(), SlurmError> {
+    let mut command = Command::new("scancel");
+    command.args(job_ids.iter().map(|job_id| job_id.to_string()));
+
+    retry_transient(|| {
+        let output: OutputUtf8 = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|err| CommandError::new_io(&command, err))
+            .map_err(SlurmError::JobCancellation)?
+            .into();
+
+        // e.g. the allocation of a group, which was already cancelled through another member
+        let already_finished = |line: &str| line.contains("already completing or completed");
+        if !output.status.success() && output.stderr.lines().all(already_finished) {
+            return Ok(());
+        }
+        output
+            .status
+            .as_piped_command_result(&command, &output.stdout, &output.stderr)
+            .map_err(SlurmError::JobCancellation)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{state::PendingReason, *};
+
+    fn job_id(job: u64, array_task: Option<u32>) -> SlurmJobID {
+        SlurmJobID { job, array_task }
+    }
+
+    #[test]
+    fn job_ids_round_trip() {
+        for s in ["1234", "1234_5"] {
+            assert_eq!(SlurmJobID::from_str(s).unwrap().to_string(), s);
+        }
+        assert!(SlurmJobID::from_str("abc").is_err());
+        assert!(SlurmJobID::from_str("1234_x").is_err());
+    }
+
+    #[test]
+    fn compressed_array_tasks_are_expanded() {
+        assert_eq!(
+            SlurmJobID::expand("1234_[0-2,5%2]").unwrap(),
+            [0, 1, 2, 5].map(|task| job_id(1234, Some(task)))
+        );
+        assert_eq!(
+            SlurmJobID::expand("1234_7").unwrap(),
+            [job_id(1234, Some(7))]
+        );
+        assert!(SlurmJobID::expand("1234_[0-2").is_err());
+        assert!(SlurmJobID::expand("1234_[a-2]").is_err());
+    }
+
+    #[test]
+    fn squeue_array_output_is_parsed() {
+        let output = "\
+1240 R None
+1241_0 R None
+1241_1 PD Priority
+1241_[2-3%1] PD JobArrayTaskLimit
+1242 PD Dependency
+1243 PD None
+1244 F NonZeroExitCode
+1245 TO TimeLimit
+1246 OOM OutOfMemory
+1247 CG NonZeroExitCode
+1248 PD ReqNodeNotAvail, UnavailableNodes:n1
+";
+        let states = parse_job_states(output)
+            .into_iter()
+            .map(|(job_id, state)| (job_id, state.unwrap()))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(states.len(), 12);
+        assert!(matches!(states[&job_id(1240, None)], JobState::Running));
+        assert!(matches!(states[&job_id(1241, Some(0))], JobState::Running));
+        assert!(matches!(
+            states[&job_id(1241, Some(1))],
+            JobState::Pending {
+                reason: Some(PendingReason::Priority)
+            }
+        ));
+        assert!(matches!(
+            states[&job_id(1241, Some(3))],
+            JobState::Pending {
+                reason: Some(PendingReason::JobArrayTaskLimit)
+            }
+        ));
+        assert!(matches!(
+            states[&job_id(1242, None)],
+            JobState::Pending {
+                reason: Some(PendingReason::Dependency)
+            }
+        ));
+        assert!(matches!(
+            states[&job_id(1243, None)],
+            JobState::Pending { reason: None }
+        ));
+        assert!(matches!(states[&job_id(1244, None)], JobState::Failed));
+        assert!(matches!(states[&job_id(1245, None)], JobState::Timeout));
+        assert!(matches!(states[&job_id(1246, None)], JobState::OutOfMemory));
+        assert!(matches!(states[&job_id(1247, None)], JobState::Completing));
+        assert!(matches!(
+            states[&job_id(1248, None)],
+            JobState::Pending {
+                reason: Some(PendingReason::ReqNodeNotAvail)
+            }
+        ));
+    }
+
+    #[test]
+    fn malformed_squeue_lines_only_fail_their_jobs() {
+        let output = "\
+1240 R None
+1241 XX None
+1242
+1243_[x] PD Priority
+garbage
+";
+        let states = parse_job_states(output);
+        assert_eq!(states.len(), 4);
+        assert!(matches!(states[&job_id(1240, None)], Ok(JobState::Running)));
+        assert!(states[&job_id(1241, None)].is_err());
+        assert!(states[&job_id(1242, None)].is_err());
+        assert!(states[&job_id(1243, None)].is_err());
+
+        let states = states
+            .into_iter()
+            .map(|(job_id, state)| {
+                let state = state.map_err(|error| SlurmError::JobStateParsing {
+                    command: String::from("squeue"),
+                    error,
+                });
+                (job_id, state)
+            })
+            .collect::<ListedStates>();
+        assert!(listed_state(&states, job_id(1243, Some(2))).is_some_and(Result::is_err));
+        assert!(listed_state(&states, job_id(1240, Some(2))).is_none());
+    }
+}
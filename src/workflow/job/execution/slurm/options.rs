@@ -1,24 +1,31 @@
+use derive_more::Display;
 use serde::Deserialize;
-use serde_with::{serde_as, DisplayFromStr};
-use std::{
-    str::FromStr,
-    time::Duration,
-};
+use serde_with::{DisplayFromStr, serde_as};
+use std::{fmt, num::NonZeroU32, str::FromStr, time::Duration};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ByteCountUnit {
-    KiloBytes,
-    MegaBytes,
-    GigaBytes,
-    TerraBytes,
+    Kilo,
+    Mega,
+    Giga,
+    Tera,
 }
 impl ByteCountUnit {
     pub fn as_slurm_suffix(self) -> &'static str {
         match self {
-            Self::KiloBytes => "K",
-            Self::MegaBytes => "M",
-            Self::GigaBytes => "G",
-            Self::TerraBytes => "T",
+            Self::Kilo => "K",
+            Self::Mega => "M",
+            Self::Giga => "G",
+            Self::Tera => "T",
+        }
+    }
+
+    fn kilo_bytes(self) -> u64 {
+        match self {
+            Self::Kilo => 1,
+            Self::Mega => 1 << 10,
+            Self::Giga => 1 << 20,
+            Self::Tera => 1 << 30,
         }
     }
 }
@@ -26,25 +33,67 @@ impl FromStr for ByteCountUnit {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "kB" => Ok(Self::KiloBytes),
-            "MB" => Ok(Self::MegaBytes),
-            "GB" => Ok(Self::GigaBytes),
-            "TB" => Ok(Self::TerraBytes),
+            "kB" => Ok(Self::Kilo),
+            "MB" => Ok(Self::Mega),
+            "GB" => Ok(Self::Giga),
+            "TB" => Ok(Self::Tera),
             _ => Err(()),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum MemorySize {
     AllAvailable,
     Fixed((u16, ByteCountUnit)),
 }
+impl MemorySize {
+    pub fn as_slurm_size(self) -> String {
+        match self {
+            Self::AllAvailable => "0".to_owned(),
+            Self::Fixed((size, unit)) => format!("{size}{unit}", unit = unit.as_slurm_suffix()),
+        }
+    }
+
+    pub(super) fn kilo_bytes(self) -> Option<u64> {
+        match self {
+            Self::AllAvailable => None,
+            Self::Fixed((size, unit)) => Some(size as u64 * unit.kilo_bytes()),
+        }
+    }
+
+    // rounds up to the smallest unit in which the size still fits
+    pub(super) fn from_kilo_bytes(kilo_bytes: u64) -> Self {
+        for unit in [
+            ByteCountUnit::Kilo,
+            ByteCountUnit::Mega,
+            ByteCountUnit::Giga,
+            ByteCountUnit::Tera,
+        ] {
+            if let Ok(size) = u16::try_from(kilo_bytes.div_ceil(unit.kilo_bytes())) {
+                return Self::Fixed((size, unit));
+            }
+        }
+
+        Self::Fixed((u16::MAX, ByteCountUnit::Tera))
+    }
+
+    // all available memory can not be scaled any further
+    fn scaled(self, factor: f64, maximum: Option<MemorySize>) -> Option<Self> {
+        let kilo_bytes = (self.kilo_bytes()? as f64 * factor).ceil() as u64;
+        Some(Self::from_kilo_bytes(
+            match maximum.and_then(|maximum| maximum.kilo_bytes()) {
+                Some(maximum) => kilo_bytes.min(maximum),
+                None => kilo_bytes,
+            },
+        ))
+    }
+}
 impl FromStr for MemorySize {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s == "all_available" {
-            return Ok(Self::AllAvailable)
+            return Ok(Self::AllAvailable);
         }
 
         for unit in ["kB", "MB", "GB", "TB"] {
@@ -76,6 +125,298 @@ impl FromStr for MemorySize {
     }
 }
 
+// names of nodes, partitions, licenses and the like only consist of these characters
+fn is_valid_slurm_name(name: &str) -> bool {
+    return !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+}
+
+fn parse_resource_count(count: &str, resource: &str) -> Result<u32, String> {
+    let count = u32::from_str(count).map_err(|err| {
+        format!(
+            "failed to parse the count `{count}` of `{resource}` as an unsigned \
+            32-bit integer (no whitespace allowed)\n{err}"
+        )
+    })?;
+    if count == 0 {
+        return Err(format!(
+            "found zero as the count of `{resource}`, but counts should always be non-zero"
+        ));
+    }
+
+    return Ok(count);
+}
+
+// generic resources as understood by `--gres`, i.e. `name[:type][:count]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenericResource {
+    name: String,
+    kind: Option<String>,
+    count: Option<u32>,
+}
+impl FromStr for GenericResource {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().expect("split always yields at least one part");
+        if !is_valid_slurm_name(name) {
+            return Err(format!(
+                "expected a generic resource of the form `name[:type][:count]`, \
+                but `{name}` is no valid resource name in `{s}`"
+            ));
+        }
+
+        let (kind, count) = match (parts.next(), parts.next(), parts.next()) {
+            (None, _, _) => (None, None),
+            (Some(last), None, _) if last.chars().all(|c| c.is_ascii_digit()) => {
+                (None, Some(parse_resource_count(last, s)?))
+            }
+            (Some(kind), None, _) => (Some(kind), None),
+            (Some(kind), Some(count), None) => (Some(kind), Some(parse_resource_count(count, s)?)),
+            (Some(_), Some(_), Some(_)) => {
+                return Err(format!(
+                    "expected a generic resource of the form `name[:type][:count]`, \
+                    but found too many colons in `{s}`"
+                ));
+            }
+        };
+        if let Some(kind) = kind.filter(|kind| !is_valid_slurm_name(kind)) {
+            return Err(format!(
+                "`{kind}` is no valid type of the generic resource `{s}`"
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            kind: kind.map(str::to_owned),
+            count,
+        })
+    }
+}
+impl fmt::Display for GenericResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(kind) = &self.kind {
+            write!(f, ":{kind}")?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ":{count}")?;
+        }
+        Ok(())
+    }
+}
+
+// licenses as understood by `--licenses`, i.e. `name[@server][:count]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct License {
+    name: String,
+    count: Option<u32>,
+}
+impl FromStr for License {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, count) = match s.split_once(':') {
+            Some((name, count)) => (name, Some(parse_resource_count(count, s)?)),
+            None => (s, None),
+        };
+
+        let valid_name = match name.split_once('@') {
+            Some((license, server)) => is_valid_slurm_name(license) && is_valid_slurm_name(server),
+            None => is_valid_slurm_name(name),
+        };
+        if !valid_name {
+            return Err(format!(
+                "expected a license of the form `name[@server][:count]`, \
+                but `{name}` is no valid license name in `{s}`"
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            count,
+        })
+    }
+}
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.count {
+            Some(count) => write!(f, "{}:{count}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// the subset of the formats accepted by `--begin` which does not depend on the locale, i.e.
+// `now+<count>[seconds|minutes|hours|days|weeks]`, `HH:MM[:SS]`, `YYYY-MM-DD[THH:MM[:SS]]` and
+// the keywords midnight, noon, teatime, today and tomorrow
+#[derive(Clone, Debug, PartialEq, Display)]
+pub struct BeginTime(String);
+impl BeginTime {
+    fn is_valid_time(time: &str) -> bool {
+        let parts = time.split(':').collect::<Vec<_>>();
+        let limits = [24, 60, 60];
+        return (2..=3).contains(&parts.len())
+            && parts.iter().zip(limits).all(|(part, limit)| {
+                part.len() == 2 && u32::from_str(part).is_ok_and(|value| value < limit)
+            });
+    }
+
+    fn is_valid_date(date: &str) -> bool {
+        let parts = date.split('-').collect::<Vec<_>>();
+        let lengths = [4, 2, 2];
+        return parts.len() == 3
+            && parts
+                .iter()
+                .zip(lengths)
+                .all(|(part, length)| part.len() == length && u32::from_str(part).is_ok())
+            && (1..=12).contains(&u32::from_str(parts[1]).expect("checked above"))
+            && (1..=31).contains(&u32::from_str(parts[2]).expect("checked above"));
+    }
+
+    fn is_valid_offset(offset: &str) -> bool {
+        let count_length = offset
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(offset.len());
+        let (count, unit) = offset.split_at(count_length);
+        return !count.is_empty()
+            && ["", "seconds", "minutes", "hours", "days", "weeks"].contains(&unit);
+    }
+}
+impl FromStr for BeginTime {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = match s {
+            "now" | "midnight" | "noon" | "teatime" | "today" | "tomorrow" => true,
+            _ => match (s.strip_prefix("now+"), s.split_once('T')) {
+                (Some(offset), _) => Self::is_valid_offset(offset),
+                (None, Some((date, time))) => {
+                    Self::is_valid_date(date) && Self::is_valid_time(time)
+                }
+                (None, None) => Self::is_valid_date(s) || Self::is_valid_time(s),
+            },
+        };
+        if !valid {
+            return Err(format!(
+                "expected a begin time of the form \
+                `now+<count>[seconds|minutes|hours|days|weeks]`, `HH:MM[:SS]`, \
+                `YYYY-MM-DD[THH:MM[:SS]]`, `midnight`, `noon`, `teatime`, `today` or `tomorrow`, \
+                got `{s}`"
+            ));
+        }
+
+        Ok(Self(s.to_owned()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+pub enum MailType {
+    #[display("NONE")]
+    None,
+    #[display("BEGIN")]
+    Begin,
+    #[display("END")]
+    End,
+    #[display("FAIL")]
+    Fail,
+    #[display("REQUEUE")]
+    Requeue,
+    #[display("ALL")]
+    All,
+    #[display("INVALID_DEPEND")]
+    InvalidDepend,
+    #[display("STAGE_OUT")]
+    StageOut,
+    #[display("TIME_LIMIT")]
+    TimeLimit,
+    #[serde(rename = "time_limit_90")]
+    #[display("TIME_LIMIT_90")]
+    TimeLimit90,
+    #[serde(rename = "time_limit_80")]
+    #[display("TIME_LIMIT_80")]
+    TimeLimit80,
+    #[serde(rename = "time_limit_50")]
+    #[display("TIME_LIMIT_50")]
+    TimeLimit50,
+    #[display("ARRAY_TASKS")]
+    ArrayTasks,
+}
+
+// sbatch options which nixflow sets itself, either because it relies on them or because there is
+// a dedicated option for them
+const RESERVED_SBATCH_OPTIONS: &[(&str, Option<&str>)] = &[
+    ("account", Some("account")),
+    ("qos", Some("quality_of_service")),
+    ("constraint", Some("constraint")),
+    ("partition", Some("partitions")),
+    ("time", Some("runtime")),
+    ("mem", Some("memory_size")),
+    ("mem-per-cpu", Some("memory_per_cpu")),
+    ("cpus-per-task", Some("cpu_count")),
+    ("gpus", Some("gpu_count")),
+    ("nodes", Some("node_count")),
+    ("ntasks", Some("task_count")),
+    ("ntasks-per-node", Some("tasks_per_node")),
+    ("gres", Some("generic_resources")),
+    ("exclusive", Some("exclusive")),
+    ("reservation", Some("reservation")),
+    ("exclude", Some("excluded_nodes")),
+    ("nodelist", Some("node_list")),
+    ("licenses", Some("licenses")),
+    ("begin", Some("begin")),
+    ("mail-type", Some("mail_types")),
+    ("mail-user", Some("mail_user")),
+    ("job-name", Some("job_name")),
+    ("comment", Some("comment")),
+    ("output", None),
+    ("error", None),
+    ("input", None),
+    ("array", None),
+    ("dependency", None),
+    ("kill-on-invalid-dep", None),
+    ("parsable", None),
+    ("wrap", None),
+    ("chdir", None),
+];
+
+// arguments passed on to sbatch as they are, which have to be long options so that they can be
+// checked against the options nixflow sets itself
+#[derive(Clone, Debug, PartialEq, Display)]
+pub struct SbatchArgument(String);
+impl FromStr for SbatchArgument {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(option) = s.strip_prefix("--") else {
+            return Err(format!(
+                "expected an sbatch argument of the form `--option[=value]`, got `{s}` \
+                (short options and separate values are not supported)"
+            ));
+        };
+
+        let name = option.split_once('=').map_or(option, |(name, _)| name);
+        let valid_character = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-';
+        if name.is_empty() || !name.chars().all(valid_character) {
+            return Err(format!("`{name}` is no valid sbatch option name in `{s}`"));
+        }
+
+        match RESERVED_SBATCH_OPTIONS
+            .iter()
+            .find(|(reserved, _)| *reserved == name)
+        {
+            Some((_, Some(dedicated))) => Err(format!(
+                "`--{name}` can not be passed as an extra argument, \
+                use the `{dedicated}` option instead"
+            )),
+            Some((_, None)) => Err(format!(
+                "`--{name}` can not be passed as an extra argument, since nixflow sets it itself"
+            )),
+            None => Ok(Self(s.to_owned())),
+        }
+    }
+}
+
 pub trait FormatSlurmTime {
     fn format_slurm_time(&self) -> String;
 }
@@ -89,8 +430,49 @@ impl FormatSlurmTime for Duration {
     }
 }
 
+// jobs which ran out of memory or time are resubmitted with more of the respective resource,
+// until the maximum number of attempts is reached
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts")]
+    pub(super) max_attempts: u32,
+
+    #[serde(default = "default_multiplier")]
+    pub(super) memory_multiplier: f64,
+
+    #[serde(default = "default_multiplier")]
+    pub(super) runtime_multiplier: f64,
+
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) max_memory_size: Option<MemorySize>,
+
+    #[serde(default)]
+    pub(super) max_runtime: Option<Duration>,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_max_attempts(),
+            memory_multiplier: default_multiplier(),
+            runtime_multiplier: default_multiplier(),
+            max_memory_size: None,
+            max_runtime: None,
+        }
+    }
+}
+
+fn default_max_attempts() -> u32 {
+    1
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub struct SlurmExecutionOptions {
     pub(super) account: String,
 
@@ -105,11 +487,411 @@ pub struct SlurmExecutionOptions {
 
     pub(super) runtime: Duration,
 
-    #[serde_as(as = "DisplayFromStr")]
-    pub(super) memory_size: MemorySize,
+    // at most one of the total memory size and the memory size per cpu can be given, slurm falls
+    // back to its default if neither is
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) memory_size: Option<MemorySize>,
+
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) memory_per_cpu: Option<MemorySize>,
 
     pub(super) cpu_count: u16,
 
     #[serde(default)]
     pub(super) gpu_count: u16,
+
+    #[serde(default)]
+    pub(super) node_count: Option<NonZeroU32>,
+
+    #[serde(default)]
+    pub(super) task_count: Option<NonZeroU32>,
+
+    #[serde(default)]
+    pub(super) tasks_per_node: Option<NonZeroU32>,
+
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) generic_resources: Vec<GenericResource>,
+
+    #[serde(default)]
+    pub(super) exclusive: bool,
+
+    #[serde(default)]
+    pub(super) reservation: Option<String>,
+
+    #[serde(default)]
+    pub(super) excluded_nodes: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub(super) node_list: Option<Vec<String>>,
+
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) licenses: Vec<License>,
+
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) begin: Option<BeginTime>,
+
+    #[serde(default)]
+    pub(super) mail_types: Vec<MailType>,
+
+    #[serde(default)]
+    pub(super) mail_user: Option<String>,
+
+    #[serde(default)]
+    pub(super) job_name: Option<String>,
+
+    #[serde(default)]
+    pub(super) comment: Option<String>,
+
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    #[serde(default)]
+    pub(super) extra_args: Vec<SbatchArgument>,
+
+    #[serde(default)]
+    pub(super) retry: RetryPolicy,
+
+    // how many jobs of a group run at the same time within its allocation
+    #[serde(default = "default_group_parallelism")]
+    pub(super) group_parallelism: u32,
+
+    // whether the job runs within node-local scratch space, see `scratch_shell_command`
+    #[serde(default)]
+    pub(super) scratch: bool,
+}
+impl SlurmExecutionOptions {
+    // checks the constraints between options, which can not be expressed by their types alone
+    pub fn validate(&self) -> Result<(), String> {
+        if self.memory_size.is_some() && self.memory_per_cpu.is_some() {
+            return Err("only one of `memory_size` and `memory_per_cpu` can be given".to_owned());
+        }
+
+        if self.memory_per_cpu == Some(MemorySize::AllAvailable) {
+            return Err(
+                "`memory_per_cpu` can not be `all_available`, use `memory_size` instead".to_owned(),
+            );
+        }
+
+        let node_names = self
+            .excluded_nodes
+            .iter()
+            .chain(self.node_list.iter())
+            .flatten();
+        for name in self.partitions.iter().flatten().chain(node_names) {
+            if !is_valid_slurm_name(name) {
+                return Err(format!("`{name}` is no valid slurm partition or node name"));
+            }
+        }
+
+        let invalid_job_name = |name: &String| name.is_empty() || name.contains(char::is_control);
+        if let Some(name) = self.job_name.as_ref().filter(|name| invalid_job_name(name)) {
+            return Err(format!("`{name}` is no valid slurm job name"));
+        }
+
+        if self
+            .comment
+            .as_ref()
+            .is_some_and(|comment| comment.contains(char::is_control))
+        {
+            return Err("the slurm job comment can not contain control characters".to_owned());
+        }
+
+        let invalid_mail_user =
+            |user: &String| user.is_empty() || user.contains(char::is_whitespace);
+        if self.mail_user.as_ref().is_some_and(invalid_mail_user) {
+            return Err(
+                "the mail user has to be a non-empty name or address without whitespace".to_owned(),
+            );
+        }
+
+        if self.retry.max_attempts < 1 {
+            return Err("the retry policy needs `max_attempts` of at least 1".to_owned());
+        }
+
+        let multipliers = [
+            ("memory_multiplier", self.retry.memory_multiplier),
+            ("runtime_multiplier", self.retry.runtime_multiplier),
+        ];
+        for (name, multiplier) in multipliers {
+            if !multiplier.is_finite() || multiplier < 1.0 {
+                return Err(format!(
+                    "the retry policy needs a finite `{name}` of at least 1, got `{multiplier}`"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // jobs can only share an allocation if they may run on the same nodes at the same time, since
+    // the allocation is submitted with the options of the first job otherwise
+    pub(super) fn shares_allocation_with(&self, other: &Self) -> bool {
+        self.account == other.account
+            && self.quality_of_service == other.quality_of_service
+            && self.partitions == other.partitions
+            && self.constraint == other.constraint
+            && self.generic_resources == other.generic_resources
+            && self.reservation == other.reservation
+            && self.node_list == other.node_list
+            && self.excluded_nodes == other.excluded_nodes
+            && self.exclusive == other.exclusive
+            && self.begin == other.begin
+            && self.extra_args == other.extra_args
+    }
+
+    // returns nothing if the resource which ran out can not be increased any further
+    pub(super) fn escalated(&self, out_of_memory: bool) -> Option<Self> {
+        let mut escalated = self.clone();
+        if out_of_memory {
+            // the maximum memory size applies to the memory per cpu as well, if that is given
+            let (escalated_memory, memory) = match self.memory_per_cpu {
+                Some(memory) => (&mut escalated.memory_per_cpu, memory),
+                None => (&mut escalated.memory_size, self.memory_size?),
+            };
+            let scaled = memory.scaled(self.retry.memory_multiplier, self.retry.max_memory_size)?;
+            if scaled.kilo_bytes() <= memory.kilo_bytes() {
+                return None;
+            }
+            *escalated_memory = Some(scaled);
+        } else {
+            // a runtime too long to be represented is capped by the maximum runtime as well
+            let runtime = Duration::try_from_secs_f64(
+                self.runtime.as_secs_f64() * self.retry.runtime_multiplier,
+            );
+            escalated.runtime = match (runtime, self.retry.max_runtime) {
+                (Ok(runtime), Some(max_runtime)) => runtime.min(max_runtime),
+                (Ok(runtime), None) => runtime,
+                (Err(_), max_runtime) => max_runtime?,
+            };
+            if escalated.runtime <= self.runtime {
+                return None;
+            }
+        }
+
+        Some(escalated)
+    }
+}
+
+fn default_group_parallelism() -> u32 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(extra: serde_json::Value) -> SlurmExecutionOptions {
+        let mut options = serde_json::json!({
+            "account": "project",
+            "runtime": { "secs": 3600, "nanos": 0 },
+            "cpu_count": 1,
+        });
+        options
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(options).unwrap()
+    }
+
+    #[test]
+    fn generic_resources_are_parsed() {
+        for gres in [
+            "gpu",
+            "gpu:2",
+            "gpu:a100",
+            "gpu:a100:2",
+            "shard:tesla_v100:16",
+        ] {
+            assert_eq!(GenericResource::from_str(gres).unwrap().to_string(), gres);
+        }
+        let gres = GenericResource::from_str("gpu:a100:2").unwrap();
+        assert_eq!(gres.kind.as_deref(), Some("a100"));
+        assert_eq!(gres.count, Some(2));
+
+        for gres in [
+            "",
+            "gpu:",
+            "gpu:a100:",
+            "gpu:a100:2:1",
+            "gpu:a100:x",
+            "gpu,mps",
+        ] {
+            assert!(
+                GenericResource::from_str(gres).is_err(),
+                "`{gres}` was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn licenses_are_parsed() {
+        for license in ["matlab", "matlab:2", "matlab@db", "matlab@db:2"] {
+            assert_eq!(License::from_str(license).unwrap().to_string(), license);
+        }
+
+        for license in ["", "matlab:", "matlab:x", "@db", "matlab@", "matlab,ansys"] {
+            assert!(
+                License::from_str(license).is_err(),
+                "`{license}` was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn begin_times_are_parsed() {
+        for begin in [
+            "now",
+            "now+60",
+            "now+2hours",
+            "teatime",
+            "16:00",
+            "16:00:30",
+            "2026-10-16",
+            "2026-10-16T16:00",
+            "2026-10-16T16:00:30",
+        ] {
+            assert_eq!(BeginTime::from_str(begin).unwrap().to_string(), begin);
+        }
+
+        for begin in [
+            "",
+            "now+",
+            "now+2fortnights",
+            "24:00",
+            "16:60",
+            "16",
+            "2026-13-01",
+            "2026-10-32",
+            "2026-10-16T",
+            "16.10.2026",
+            "next tuesday",
+        ] {
+            assert!(
+                BeginTime::from_str(begin).is_err(),
+                "`{begin}` was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn sbatch_arguments_are_checked() {
+        for argument in ["--hint=nomultithread", "--requeue", "--mcs-label=group"] {
+            assert_eq!(
+                SbatchArgument::from_str(argument).unwrap().to_string(),
+                argument
+            );
+        }
+
+        for argument in ["-N2", "--", "--Hint=x", "hint=nomultithread"] {
+            assert!(
+                SbatchArgument::from_str(argument).is_err(),
+                "`{argument}` was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_options_are_rejected_in_extra_args() {
+        let err = SbatchArgument::from_str("--time=01:00:00").unwrap_err();
+        assert!(err.contains("`runtime`"), "unexpected error: {err}");
+        let err = SbatchArgument::from_str("--parsable").unwrap_err();
+        assert!(
+            err.contains("nixflow sets it itself"),
+            "unexpected error: {err}"
+        );
+
+        let mut options = serde_json::json!({
+            "account": "project",
+            "runtime": { "secs": 3600, "nanos": 0 },
+            "cpu_count": 1,
+            "extra_args": ["--requeue", "--partition=gpu"],
+        });
+        assert!(serde_json::from_value::<SlurmExecutionOptions>(options.clone()).is_err());
+        options["extra_args"] = serde_json::json!(["--requeue"]);
+        assert!(serde_json::from_value::<SlurmExecutionOptions>(options).is_ok());
+    }
+
+    #[test]
+    fn retry_policies_are_validated() {
+        assert!(options(serde_json::json!({})).validate().is_ok());
+        for retry in [
+            serde_json::json!({ "max_attempts": 0 }),
+            serde_json::json!({ "memory_multiplier": 0.5 }),
+            serde_json::json!({ "runtime_multiplier": -2.0 }),
+        ] {
+            assert!(
+                options(serde_json::json!({ "retry": retry }))
+                    .validate()
+                    .is_err()
+            );
+        }
+
+        let mut infinite = options(serde_json::json!({}));
+        infinite.retry.runtime_multiplier = f64::INFINITY;
+        assert!(infinite.validate().is_err());
+        infinite.retry.runtime_multiplier = f64::NAN;
+        assert!(infinite.validate().is_err());
+    }
+
+    #[test]
+    fn allocations_are_only_shared_on_the_same_nodes() {
+        let leader = options(serde_json::json!({ "partitions": ["cpu"] }));
+        let member = options(serde_json::json!({ "partitions": ["cpu"], "cpu_count": 4 }));
+        assert!(leader.shares_allocation_with(&member));
+
+        for differing in [
+            serde_json::json!({ "node_list": ["n1"] }),
+            serde_json::json!({ "excluded_nodes": ["n1"] }),
+            serde_json::json!({ "exclusive": true }),
+            serde_json::json!({ "begin": "now+1hours" }),
+            serde_json::json!({ "extra_args": ["--requeue"] }),
+        ] {
+            let mut member = serde_json::json!({ "partitions": ["cpu"] });
+            member
+                .as_object_mut()
+                .unwrap()
+                .extend(differing.as_object().unwrap().clone());
+            let member = options(member);
+            assert!(
+                !leader.shares_allocation_with(&member),
+                "`{differing}` was shared"
+            );
+        }
+    }
+
+    #[test]
+    fn escalated_runtime_is_capped() {
+        let mut options = options(serde_json::json!({
+            "retry": { "max_attempts": 3, "max_runtime": { "secs": 5400, "nanos": 0 } },
+        }));
+        let escalated = options.escalated(false).unwrap();
+        assert_eq!(escalated.runtime, Duration::from_secs(5400));
+        assert_eq!(escalated.escalated(false), None);
+
+        // a runtime too long to be represented falls back to the maximum runtime
+        options.retry.runtime_multiplier = 1e300;
+        assert_eq!(
+            options.escalated(false).unwrap().runtime,
+            Duration::from_secs(5400)
+        );
+        options.retry.max_runtime = None;
+        assert_eq!(options.escalated(false), None);
+    }
+
+    #[test]
+    fn escalated_memory_is_capped() {
+        let options = options(serde_json::json!({
+            "memory_size": "4GB",
+            "retry": { "max_attempts": 3, "max_memory_size": "6GB" },
+        }));
+        let escalated = options.escalated(true).unwrap();
+        assert_eq!(
+            escalated.memory_size.unwrap().kilo_bytes(),
+            MemorySize::from_str("6GB").unwrap().kilo_bytes()
+        );
+        assert_eq!(escalated.escalated(true), None);
+    }
 }
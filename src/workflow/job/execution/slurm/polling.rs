@@ -0,0 +1,176 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::{
+    ListedStates, SlurmError, SlurmJobID,
+    accounting::{JobAccounting, query_jobs_accounting},
+    listed_state, poll_job_states,
+    retry::{retry_delay, slurm_grace_period},
+    state::JobState,
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// all slurm jobs followed by this process are polled together, so that the number of squeue and
+// sacct invocations does not grow with the number of jobs
+static POLLER: LazyLock<Mutex<SlurmPoller>> = LazyLock::new(|| {
+    Mutex::new(SlurmPoller {
+        interval: DEFAULT_POLL_INTERVAL,
+        followers: HashMap::new(),
+        last_poll: None,
+        failures: 0,
+        failing_since: None,
+        polled: HashSet::new(),
+        states: HashMap::new(),
+        accounting: HashMap::new(),
+        accounting_unavailable: None,
+        accounting_warned: false,
+    })
+});
+
+// what the last poll found out about a job, the accounting is only queried for jobs which squeue
+// does not list as unfinished
+#[derive(Clone, Debug)]
+pub struct PolledJob {
+    pub state: Option<JobState>,
+    pub accounting: Option<JobAccounting>,
+    pub accounting_unavailable: bool,
+    // handed out only once, to whichever job is polled first after sacct turned out unavailable
+    pub warning: Option<String>,
+}
+
+#[derive(Debug)]
+struct SlurmPoller {
+    interval: Duration,
+    // several children can follow the same job, e.g. the members of a group allocation
+    followers: HashMap<SlurmJobID, usize>,
+    last_poll: Option<Instant>,
+    // polls which failed transiently in a row, which are retried with an increasing delay
+    failures: u32,
+    failing_since: Option<Instant>,
+    polled: HashSet<SlurmJobID>,
+    // jobs whose line of the squeue output could not be parsed fail on their own
+    states: ListedStates,
+    accounting: HashMap<SlurmJobID, JobAccounting>,
+    // sites without accounting storage let sacct fail permanently, after which it is not queried
+    // anymore
+    accounting_unavailable: Option<SlurmError>,
+    accounting_warned: bool,
+}
+impl SlurmPoller {
+    fn poll_due(&self) -> bool {
+        let interval = self.interval.max(retry_delay(self.failures));
+        self.last_poll
+            .is_none_or(|last_poll| last_poll.elapsed() >= interval)
+    }
+
+    // a failed poll still counts as a poll, so that failures do not lead to polling more often
+    fn poll(&mut self) -> Result<(), SlurmError> {
+        self.last_poll = Some(Instant::now());
+
+        let job_ids = self.followers.keys().copied().collect::<Vec<_>>();
+        let states = poll_job_states(&job_ids)?;
+        let unlisted = job_ids
+            .iter()
+            .copied()
+            .filter(|job_id| {
+                listed_state(&states, *job_id)
+                    .is_none_or(|state| state.as_ref().is_ok_and(JobState::is_finished))
+            })
+            .collect::<Vec<_>>();
+        let accounting = match unlisted.is_empty() || self.accounting_unavailable.is_some() {
+            true => HashMap::new(),
+            false => match query_jobs_accounting(&unlisted) {
+                Err(err @ SlurmError::JobAccountingQuery(_)) if !err.is_transient() => {
+                    self.accounting_unavailable = Some(err);
+                    HashMap::new()
+                }
+                result => result?,
+            },
+        };
+
+        self.polled = job_ids.into_iter().collect();
+        self.states = states;
+        self.accounting = accounting;
+        Ok(())
+    }
+}
+
+// sites usually limit how often their slurm controller may be queried
+pub fn set_slurm_poll_interval(interval: Duration) {
+    POLLER
+        .lock()
+        .expect("poller lock is never poisoned")
+        .interval = interval;
+}
+
+pub fn slurm_poll_interval() -> Duration {
+    POLLER
+        .lock()
+        .expect("poller lock is never poisoned")
+        .interval
+}
+
+pub fn follow_slurm_job(job_id: SlurmJobID) {
+    let mut poller = POLLER.lock().expect("poller lock is never poisoned");
+    *poller.followers.entry(job_id).or_default() += 1;
+}
+
+pub fn unfollow_slurm_job(job_id: SlurmJobID) {
+    let mut poller = POLLER.lock().expect("poller lock is never poisoned");
+    if let Some(followers) = poller.followers.get_mut(&job_id) {
+        *followers -= 1;
+        if *followers == 0 {
+            poller.followers.remove(&job_id);
+        }
+    }
+}
+
+// returns nothing if the job was not polled yet, because it was followed only after the last poll
+// and the next poll is not due yet
+pub fn polled_job(job_id: SlurmJobID) -> Result<Option<PolledJob>, SlurmError> {
+    let mut poller = POLLER.lock().expect("poller lock is never poisoned");
+    assert!(
+        poller.followers.contains_key(&job_id),
+        "only followed jobs are polled"
+    );
+    if poller.poll_due() {
+        match poller.poll() {
+            Ok(()) => {
+                poller.failures = 0;
+                poller.failing_since = None;
+            }
+            // the results of the last successful poll are kept until slurm is reachable again
+            Err(err) if err.is_transient() => {
+                let failing_since = *poller.failing_since.get_or_insert_with(Instant::now);
+                if failing_since.elapsed() >= slurm_grace_period() {
+                    return Err(err);
+                }
+                poller.failures += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    if !poller.polled.contains(&job_id) {
+        return Ok(None);
+    }
+    let state = listed_state(&poller.states, job_id).cloned().transpose()?;
+    let warning = match (&poller.accounting_unavailable, poller.accounting_warned) {
+        (Some(err), false) => Some(format!(
+            "the slurm accounting is unavailable, which is why jobs which left the queue are \
+            assumed to have completed\n{err}"
+        )),
+        _ => None,
+    };
+    poller.accounting_warned |= warning.is_some();
+    Ok(Some(PolledJob {
+        state,
+        accounting: poller.accounting.get(&job_id).cloned(),
+        accounting_unavailable: poller.accounting_unavailable.is_some(),
+        warning,
+    }))
+}
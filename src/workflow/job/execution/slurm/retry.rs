@@ -0,0 +1,141 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::workflow::interrupt::interrupted;
+
+use super::SlurmError;
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(300);
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+static GRACE_PERIOD: Mutex<Duration> = Mutex::new(DEFAULT_GRACE_PERIOD);
+
+// how long slurm may keep failing transiently (e.g. while the controller restarts), before the
+// failure is considered permanent
+pub fn set_slurm_grace_period(grace_period: Duration) {
+    *GRACE_PERIOD
+        .lock()
+        .expect("grace period lock is never poisoned") = grace_period;
+}
+
+pub fn slurm_grace_period() -> Duration {
+    *GRACE_PERIOD
+        .lock()
+        .expect("grace period lock is never poisoned")
+}
+
+// the delay doubles with every failure in a row
+pub fn retry_delay(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+
+    INITIAL_RETRY_DELAY
+        .saturating_mul(1 << (failures - 1).min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+impl SlurmError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::JobStatePoll(err)
+            | Self::JobExecute(err)
+            | Self::JobAccountingQuery(err)
+            | Self::JobCancellation(err) => err.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+// blocks until the operation succeeds, fails permanently, keeps failing transiently for longer
+// than the grace period or is interrupted; operations which started after an interrupt (e.g.
+// cancelling all jobs) are retried regardless, since a second interrupt exits right away
+pub fn retry_transient<T>(
+    mut operation: impl FnMut() -> Result<T, SlurmError>,
+) -> Result<T, SlurmError> {
+    let start = Instant::now();
+    let interruptible = !interrupted();
+    let mut failures = 0;
+    loop {
+        match operation() {
+            Err(err) if err.is_transient() && start.elapsed() < slurm_grace_period() => {
+                failures += 1;
+                let retry_time = Instant::now() + retry_delay(failures);
+                while Instant::now() < retry_time {
+                    if interruptible && interrupted() {
+                        return Err(err);
+                    }
+                    thread::sleep(
+                        INTERRUPT_CHECK_INTERVAL
+                            .min(retry_time.saturating_duration_since(Instant::now())),
+                    );
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+// the outcome of an operation which runs on a thread of its own, so that retrying it does not hold
+// up the scheduler (e.g. noticing that local jobs exited in the meantime)
+#[derive(Debug)]
+pub struct Background<T>(Arc<BackgroundOutcome<T>>);
+
+#[derive(Debug)]
+struct BackgroundOutcome<T> {
+    outcome: Mutex<Option<Result<T, SlurmError>>>,
+    finished: Condvar,
+}
+
+impl<T> Clone for Background<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<T: Clone + Send + 'static> Background<T> {
+    pub fn spawn(operation: impl FnOnce() -> Result<T, SlurmError> + Send + 'static) -> Self {
+        let shared = Arc::new(BackgroundOutcome {
+            outcome: Mutex::new(None),
+            finished: Condvar::new(),
+        });
+        let background = Self(shared.clone());
+        thread::spawn(move || {
+            let result = operation();
+            *shared
+                .outcome
+                .lock()
+                .expect("outcome lock is never poisoned") = Some(result);
+            shared.finished.notify_all();
+        });
+        background
+    }
+
+    // returns nothing while the operation is still running
+    pub fn try_outcome(&self) -> Option<Result<T, SlurmError>> {
+        self.0
+            .outcome
+            .lock()
+            .expect("outcome lock is never poisoned")
+            .clone()
+    }
+
+    pub fn outcome(&self) -> Result<T, SlurmError> {
+        let outcome = self
+            .0
+            .finished
+            .wait_while(
+                self.0
+                    .outcome
+                    .lock()
+                    .expect("outcome lock is never poisoned"),
+                |outcome| outcome.is_none(),
+            )
+            .expect("outcome lock is never poisoned");
+        outcome.clone().expect("waited for the outcome above")
+    }
+}
@@ -0,0 +1,159 @@
+use camino::{Utf8Component, Utf8Path as Path, Utf8PathBuf as PathBuf};
+
+use crate::workflow::job::JobError;
+
+// suffix of the copies of the outputs next to their destination, which are only moved into place
+// once all outputs were copied back
+const STAGED_OUTPUT_SUFFIX: &str = ".nixflow-staged";
+
+pub(super) fn quote(path: &Path) -> String {
+    format!("'{}'", path.as_str().replace('\'', "'\\''"))
+}
+
+// paths are staged relative to the working directory, which is why absolute paths, which the
+// command refers to directly, are left where they are
+fn staged_paths(paths: &[PathBuf]) -> impl Iterator<Item = &PathBuf> {
+    paths.iter().filter(|path| path.is_relative())
+}
+
+fn parent_directories<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> Vec<&'a Path> {
+    let mut directories = paths
+        .filter_map(|path| path.parent())
+        .filter(|directory| !directory.as_str().is_empty())
+        .collect::<Vec<_>>();
+    directories.sort();
+    directories.dedup();
+    directories
+}
+
+// the command runs within a temporary directory on the node (`$TMPDIR`), to which the inputs are
+// copied beforehand, while the outputs are only copied back if the command succeeded, so that a
+// failure leaves the shared filesystem untouched; relative paths which leave the working directory
+// would be staged outside of the temporary directory, which is why they are rejected
+pub fn scratch_shell_command(
+    shell_command: &str,
+    inputs: &[PathBuf],
+    outputs: &[PathBuf],
+) -> Result<String, JobError> {
+    if let Some(path) = staged_paths(inputs)
+        .chain(staged_paths(outputs))
+        .find(|path| {
+            path.components()
+                .any(|component| component == Utf8Component::ParentDir)
+        })
+    {
+        return Err(JobError::ScratchStagingOutsideWorkdir(path.clone()));
+    }
+
+    let mut script = String::from(
+        "(\n\
+        workdir=\"$(pwd)\"\n\
+        scratch=\"$(mktemp -d \"${TMPDIR:-/tmp}/nixflow.XXXXXX\")\" || exit 1\n\
+        trap 'rm -rf \"$scratch\"' EXIT\n",
+    );
+
+    for directory in parent_directories(staged_paths(inputs).chain(staged_paths(outputs))) {
+        script += &format!("mkdir -p \"$scratch\"/{} || exit 1\n", quote(directory));
+    }
+    for input in staged_paths(inputs) {
+        script += &format!(
+            "cp -R {input} \"$scratch\"/{input} || exit 1\n",
+            input = quote(input)
+        );
+    }
+
+    script += &format!(
+        "cd \"$scratch\" || exit 1\n( {shell_command} ) || exit $?\ncd \"$workdir\" || exit 1\n"
+    );
+
+    let outputs = staged_paths(outputs).collect::<Vec<_>>();
+    let staged_outputs = outputs
+        .iter()
+        .map(|output| quote(&PathBuf::from(format!("{output}{STAGED_OUTPUT_SUFFIX}"))))
+        .collect::<Vec<_>>();
+    let remove_staged_outputs = format!("rm -rf {}", staged_outputs.join(" "));
+    // missing outputs are noticed before anything on the shared filesystem is touched
+    for output in outputs.iter() {
+        script += &format!(
+            "[ -e \"$scratch\"/{output} ] || {{ echo \"missing output \"{output} >&2; exit 1; }}\n",
+            output = quote(output)
+        );
+    }
+    for directory in parent_directories(outputs.iter().copied()) {
+        script += &format!("mkdir -p {} || exit 1\n", quote(directory));
+    }
+    for (output, staged_output) in outputs.iter().zip(&staged_outputs) {
+        script += &format!(
+            "rm -rf {staged_output} && cp -R \"$scratch\"/{output} {staged_output} \
+            || {{ {remove_staged_outputs}; exit 1; }}\n",
+            output = quote(output)
+        );
+    }
+    // files are replaced atomically, directories right after removing the previous ones; all
+    // outputs were copied next to their destination by now, but should moving one of them fail,
+    // those moved before it are already replaced
+    for (output, staged_output) in outputs.iter().zip(&staged_outputs) {
+        script += &format!(
+            "{{ [ ! -d {output} ] || rm -rf {output}; }} && mv -f {staged_output} {output} \
+            || {{ {remove_staged_outputs}; exit 1; }}\n",
+            output = quote(output)
+        );
+    }
+
+    script += ")";
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix_environment::{FlakeOutput, FlakeSource};
+
+    #[test]
+    fn relative_flake_path_is_resolved_outside_of_scratch() {
+        let flake_output = FlakeOutput::new(FlakeSource::Path(PathBuf::from("workflow")), "step");
+        let script = scratch_shell_command(
+            &format!("nix run {flake_output}"),
+            &[PathBuf::from("data/input.csv")],
+            &[PathBuf::from("results/output.csv")],
+        )
+        .unwrap();
+
+        let workdir = PathBuf::try_from(std::env::current_dir().unwrap()).unwrap();
+        let (_, command) = script.split_once("cd \"$scratch\" || exit 1\n").unwrap();
+        assert!(command.starts_with(&format!("( nix run {workdir}/workflow#step ) || exit $?\n")));
+        assert!(!script.contains("./workflow"));
+    }
+
+    #[test]
+    fn paths_outside_of_the_working_directory_are_rejected() {
+        for (inputs, outputs) in [
+            (vec![PathBuf::from("../data/input.csv")], vec![]),
+            (vec![], vec![PathBuf::from("results/../../output.csv")]),
+        ] {
+            assert!(matches!(
+                scratch_shell_command("true", &inputs, &outputs),
+                Err(JobError::ScratchStagingOutsideWorkdir(_))
+            ));
+        }
+        assert!(scratch_shell_command("true", &[PathBuf::from("/data/../input.csv")], &[]).is_ok());
+    }
+
+    #[test]
+    fn relative_paths_are_staged() {
+        let script = scratch_shell_command(
+            "true",
+            &[PathBuf::from("data/input.csv"), PathBuf::from("/abs/input")],
+            &[PathBuf::from("results/output.csv")],
+        )
+        .unwrap();
+
+        assert!(script.contains("mkdir -p \"$scratch\"/'data' || exit 1\n"));
+        assert!(
+            script.contains("cp -R 'data/input.csv' \"$scratch\"/'data/input.csv' || exit 1\n")
+        );
+        assert!(!script.contains("/abs/input"));
+        assert!(script.contains("[ -e \"$scratch\"/'results/output.csv' ]"));
+        assert!(script.contains("mv -f 'results/output.csv.nixflow-staged' 'results/output.csv'"));
+    }
+}
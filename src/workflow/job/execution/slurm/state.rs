@@ -1,6 +1,6 @@
 use derive_more::Display;
 
-#[derive(Display)]
+#[derive(Clone, Debug, Display)]
 pub enum JobState {
     #[display("BootFail")]
     BootFail,
@@ -58,51 +58,99 @@ pub enum JobState {
     Timeout,
 }
 impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Self::BootFail
+                | Self::Cancelled
+                | Self::Completed
+                | Self::Deadline
+                | Self::Failed
+                | Self::NodeFail
+                | Self::OutOfMemory
+                | Self::Preempted
+                | Self::Revoked
+                | Self::SpecialExit
+                | Self::Timeout
+        )
+    }
+
+    pub fn unresolvable_pending_reason(&self) -> Option<&PendingReason> {
+        match self {
+            Self::Pending {
+                reason: Some(reason),
+            } if reason.never_resolves() => Some(reason),
+            _ => None,
+        }
+    }
+
+    // sacct reports the long state names, with additional information for some of them (e.g.
+    // `CANCELLED by 1234`), but no pending reasons
+    pub fn from_accounting_state(state: &str) -> Result<Self, String> {
+        match state.split(' ').next().unwrap_or_default() {
+            "BOOT_FAIL" => Ok(JobState::BootFail),
+            "CANCELLED" => Ok(JobState::Cancelled),
+            "COMPLETED" => Ok(JobState::Completed),
+            "DEADLINE" => Ok(JobState::Deadline),
+            "FAILED" => Ok(JobState::Failed),
+            "NODE_FAIL" => Ok(JobState::NodeFail),
+            "OUT_OF_MEMORY" => Ok(JobState::OutOfMemory),
+            "PENDING" => Ok(JobState::Pending { reason: None }),
+            "PREEMPTED" => Ok(JobState::Preempted),
+            "RUNNING" => Ok(JobState::Running),
+            "REQUEUED" => Ok(JobState::Requeued),
+            "RESIZING" => Ok(JobState::Resizing),
+            "REVOKED" => Ok(JobState::Revoked),
+            "SUSPENDED" => Ok(JobState::Suspended),
+            "TIMEOUT" => Ok(JobState::Timeout),
+            _ => Err(format!("encountered invalid job state `{state}`")),
+        }
+    }
+
+    // the reason is only meaningful for pending jobs, for others squeue reports e.g. why they
+    // failed (`NonZeroExitCode`) or `None`; reasons may contain spaces and further details (e.g.
+    // `ReqNodeNotAvail, UnavailableNodes:n1`)
     pub fn from_polling_output(output: &str) -> Result<Self, String> {
-        match output.split(" ").collect::<Vec<_>>().as_slice() {
-            ["BF", "None"] => Ok(JobState::BootFail),
-            ["CA", "None"] => Ok(JobState::Cancelled),
-            ["CD", "None"] => Ok(JobState::Completed),
-            ["CF", "None"] => Ok(JobState::Configuring),
-            ["CG", "None"] => Ok(JobState::Completing),
-            ["DL", "None"] => Ok(JobState::Deadline),
-            ["F", "None"] => Ok(JobState::Failed),
-            ["NF", "None"] => Ok(JobState::NodeFail),
-            ["OOM", "None"] => Ok(JobState::OutOfMemory),
-            ["PD", reason] if *reason != "None" => Ok(JobState::Pending {
-                reason: PendingReason::from_polling_output(reason).map_err(|err| {
+        let (code, reason) = output.split_once(' ').ok_or_else(|| {
+            format!(
+                "encountered `{output}` which does not match the expected format `<code> <reason>`"
+            )
+        })?;
+        match code {
+            "BF" => Ok(JobState::BootFail),
+            "CA" => Ok(JobState::Cancelled),
+            "CD" => Ok(JobState::Completed),
+            "CF" => Ok(JobState::Configuring),
+            "CG" => Ok(JobState::Completing),
+            "DL" => Ok(JobState::Deadline),
+            "F" => Ok(JobState::Failed),
+            "NF" => Ok(JobState::NodeFail),
+            "OOM" => Ok(JobState::OutOfMemory),
+            "PD" => Ok(JobState::Pending {
+                reason: PendingReason::from_polling_output(reason.trim()).map_err(|err| {
                     format!("encountered pending code (PD) with invalid reason\n{err}")
                 })?,
             }),
-            ["PR", "None"] => Ok(JobState::Preempted),
-            ["R", "None"] => Ok(JobState::Running),
-            ["RD", "None"] => Ok(JobState::ResvDelHold),
-            ["RF", "None"] => Ok(JobState::RequeueFed),
-            ["RH", "None"] => Ok(JobState::RequeueHold),
-            ["RQ", "None"] => Ok(JobState::Requeued),
-            ["RS", "None"] => Ok(JobState::Resizing),
-            ["RV", "None"] => Ok(JobState::Revoked),
-            ["SI", "None"] => Ok(JobState::Signaling),
-            ["SE", "None"] => Ok(JobState::SpecialExit),
-            ["SO", "None"] => Ok(JobState::StageOut),
-            ["ST", "None"] => Ok(JobState::Stopped),
-            ["S", "None"] => Ok(JobState::Suspended),
-            ["TO", "None"] => Ok(JobState::Timeout),
-            ["PD", "None"] => Err(String::from(
-                "encountered pending code (PD) without a reason",
-            )),
-            [code, reason] if *reason != "None" => Err(format!(
-                "encountered pending reason with a non-pending code ({code})"
-            )),
-            [code, _] => Err(format!("encountered invalid status code ({code})")),
-            _ => Err(format!(
-                "encountered `{output}` which does not match the expected format `<code> <reason>`"
-            )),
+            "PR" => Ok(JobState::Preempted),
+            "R" => Ok(JobState::Running),
+            "RD" => Ok(JobState::ResvDelHold),
+            "RF" => Ok(JobState::RequeueFed),
+            "RH" => Ok(JobState::RequeueHold),
+            "RQ" => Ok(JobState::Requeued),
+            "RS" => Ok(JobState::Resizing),
+            "RV" => Ok(JobState::Revoked),
+            "SI" => Ok(JobState::Signaling),
+            "SE" => Ok(JobState::SpecialExit),
+            "SO" => Ok(JobState::StageOut),
+            "ST" => Ok(JobState::Stopped),
+            "S" => Ok(JobState::Suspended),
+            "TO" => Ok(JobState::Timeout),
+            _ => Err(format!("encountered invalid status code ({code})")),
         }
     }
 }
 
-#[derive(Display)]
+#[derive(Clone, Debug, Display)]
 pub enum PendingReason {
     #[display("accounting policy")]
     AccountingPolicy,
@@ -490,8 +538,20 @@ pub enum PendingReason {
     TimeLimit,
 }
 impl PendingReason {
+    // jobs pending for these reasons would wait forever, since slurm does not reconsider them
+    pub fn never_resolves(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidAccount
+                | Self::BadConstraints
+                | Self::DependencyNeverSatisfied
+                | Self::PartitionTimeLimit
+        )
+    }
+
+    // some reasons are followed by details, e.g. `ReqNodeNotAvail, UnavailableNodes:n1`
     pub fn from_polling_output(output: &str) -> Result<Option<Self>, String> {
-        match output {
+        match output.split(',').next().unwrap_or_default().trim() {
             "AccountingPolicy" => Ok(Some(Self::AccountingPolicy)),
             "AccountNotAllowed" => Ok(Some(Self::AccountNotAllowed)),
             "AssocGrpBB" => Ok(Some(Self::AssocGrpBB)),
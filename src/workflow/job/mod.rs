@@ -1,26 +1,36 @@
 use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
 use derive_more::Debug;
 use execution::{
-    ExecutionError, ExecutionMethod, JobExecutionChild, JobExecutionCommand, JobExecutionError,
+    ExecutionMethod, ExecutionRecord, ExitNotification, JobExecutionChild, JobExecutionCommand,
+    JobExecutionError, SlurmJobID, kill_children, submit_children,
 };
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use miette::Diagnostic;
+use resources::ResourceGrant;
+use staleness::{
+    RerunReason, RerunTrigger, changed_input_reason, recorded_run_reason, updated_input_reason,
+};
 use std::{
-    error::Error,
-    fmt::Display,
-    fs::OpenOptions,
-    io::{self, BufRead, BufReader, Write},
-    process::Command,
-    rc::Rc,
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader},
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, channel},
     },
     thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
 };
 use warnings::{ErrorCatcher, TryCatch};
 
-use crate::utils::{IoError, JoinOrPanic};
+use crate::{
+    utils::{IoError, JoinOrPanic},
+    workflow::metadata::{
+        FileFingerprint, MetadataError, OutputMetadata, RunStatus, read_fingerprints,
+        write_output_metadata,
+    },
+};
 
 use super::specification::{
     StepInfo,
@@ -28,12 +38,15 @@ use super::specification::{
 };
 
 pub mod execution;
+pub mod resources;
+pub mod staleness;
 pub mod warnings;
 
 #[derive(Debug)]
 pub enum Job {
     Pending(PendingJob),
-    Running(RunningJob),
+    // boxed since running jobs are far larger than all other jobs
+    Running(Box<RunningJob>),
     Successful(SuccessfulJob),
     Failed(FailedJob),
     Terminated(TerminatedJob),
@@ -54,14 +67,17 @@ impl Job {
         )
     }
 
-    pub fn successful(&self) -> bool {
-        matches!(self, Self::Successful(_))
-    }
-
     pub fn failed(&self) -> bool {
         matches!(self, Self::Failed(_))
     }
 
+    pub fn rerun_reason(&self) -> Option<&RerunReason> {
+        match self {
+            Self::Pending(pending) => pending.rerun_reason(),
+            _ => None,
+        }
+    }
+
     pub fn step(&self) -> &StepInfo {
         match self {
             Self::Pending(pending) => &pending.step,
@@ -77,7 +93,7 @@ impl Job {
             Self::Pending(pending) => pending.report(),
             Self::Running(running) => running.report(),
             Self::Successful(successful) => successful.report.clone(),
-            Self::Failed(failed) => failed.report.clone(),
+            Self::Failed(failed) => (*failed.report).clone(),
             Self::Terminated(terminated) => terminated.report.clone(),
         }
     }
@@ -101,23 +117,27 @@ macro_rules! impl_from_variant {
     };
 }
 impl_from_variant!(PendingJob, Pending);
-impl_from_variant!(RunningJob, Running);
 impl_from_variant!(SuccessfulJob, Successful);
 impl_from_variant!(FailedJob, Failed);
 impl_from_variant!(TerminatedJob, Terminated);
+impl From<RunningJob> for Job {
+    fn from(running: RunningJob) -> Self {
+        Job::Running(Box::new(running))
+    }
+}
 
 impl From<ExecutedJob> for Job {
     fn from(executed: ExecutedJob) -> Self {
         match executed {
             ExecutedJob::Running(running) => Job::Running(running),
-            ExecutedJob::Finished(successful) => Job::Successful(successful),
+            ExecutedJob::Finished(successful) => Job::Successful(*successful),
         }
     }
 }
 impl From<FinishedJob> for Job {
     fn from(finished: FinishedJob) -> Self {
         match finished {
-            FinishedJob::Successful(successful) => Job::Successful(successful),
+            FinishedJob::Successful(successful) => Job::Successful(*successful),
             FinishedJob::Failed(failed) => Job::Failed(failed),
         }
     }
@@ -127,15 +147,20 @@ impl From<FinishedJob> for Job {
 pub struct PendingJob {
     command: Box<dyn JobExecutionCommand>,
     pub step: StepInfo,
+    rerun_reason: Option<RerunReason>,
 }
 impl PendingJob {
     pub fn new(command: Box<dyn JobExecutionCommand>, step: StepInfo) -> Self {
-        Self { command, step }
+        Self {
+            command,
+            step,
+            rerun_reason: None,
+        }
     }
 
     pub fn non_existing_associated_paths<'p>(
         &self,
-        paths: &'p Vec<PathBuf>,
+        paths: &'p [PathBuf],
     ) -> Result<Vec<&'p Path>, (PathBuf, std::io::Error)> {
         paths
             .iter()
@@ -156,11 +181,60 @@ impl PendingJob {
             .map_err(|(path, err)| JobError::OutputExistenceCheck(path, err.into()))
     }
 
+    // determines why this job has to be rerun, disregarding the parents
+    pub fn determine_rerun_reason(
+        &self,
+        trigger: RerunTrigger,
+    ) -> Result<Option<RerunReason>, JobError> {
+        if let Some(non_existing_output) = self.non_existing_outputs()?.first() {
+            return Ok(Some(RerunReason::MissingOutput(
+                non_existing_output.to_path_buf(),
+            )));
+        }
+
+        if let Some(reason) = recorded_run_reason(&self.step.runner, &self.step.outputs)? {
+            return Ok(Some(reason));
+        }
+
+        match trigger {
+            RerunTrigger::ModificationTime => {
+                updated_input_reason(&self.step.inputs, &self.step.outputs)
+            }
+            RerunTrigger::Checksum => changed_input_reason(&self.step.inputs, &self.step.outputs),
+        }
+    }
+
+    pub fn rerun_reason(&self) -> Option<&RerunReason> {
+        self.rerun_reason.as_ref()
+    }
+
+    pub fn shell_command(&self) -> String {
+        self.command.shell_command()
+    }
+
+    pub fn execution_method(&self) -> ExecutionMethod {
+        self.command.method()
+    }
+
+    pub fn command(&self) -> &Box<dyn JobExecutionCommand> {
+        &self.command
+    }
+
+    // used to let the job attach to a slurm allocation it was submitted with ahead of time
+    pub fn replace_command(&mut self, command: Box<dyn JobExecutionCommand>) {
+        self.command = command;
+    }
+
+    pub fn set_rerun_reason(&mut self, reason: Option<RerunReason>) {
+        self.rerun_reason = reason;
+    }
+
     pub fn terminate(self) -> TerminatedJob {
         TerminatedJob::new(
             JobReport {
                 warnings: Vec::new(),
                 step: self.step,
+                execution: None,
             },
             None,
         )
@@ -169,9 +243,8 @@ impl PendingJob {
     pub fn execute(
         self,
         progress: &MultiProgress,
-        progress_style: JobProgressStyle,
-        prefer_warnings: bool,
-        inspect: bool,
+        settings: JobExecutionSettings,
+        grant: &ResourceGrant,
     ) -> Result<ExecutedJob, FailedJob> {
         let non_existing_inputs = self
             .non_existing_inputs()
@@ -183,23 +256,27 @@ impl PendingJob {
                     .map(|path| path.to_owned())
                     .collect(),
             }
-            .as_failed_job(self.report(), None));
+            .into_failed_job(self.report(), None));
         }
 
-        let non_existing_outputs = self
-            .non_existing_outputs()
-            .map_err(|err| FailedJob::new(err, self.report(), None))?;
-        if non_existing_outputs.is_empty() {
+        if self.rerun_reason.is_none() {
             return Ok(SuccessfulJob::new(
                 JobReport {
                     warnings: Vec::new(),
                     step: self.step,
+                    execution: None,
                 },
                 None,
             )
             .into());
         }
 
+        if settings.touch {
+            return self
+                .touch(progress, settings.rerun_trigger)
+                .map(|job| job.into());
+        }
+
         std::fs::create_dir_all(
             self.step
                 .log
@@ -208,43 +285,172 @@ impl PendingJob {
         )
         .map_err(|err| {
             JobError::LogFileParentDirectoryCreation(self.step.log.clone(), err.into())
-                .as_failed_job(self.report(), None)
+                .into_failed_job(self.report(), None)
         })?;
 
         let report = self.report();
         let child = self
             .command
-            .spawn()
+            .spawn(grant)
             .map_err(|err| err.into())
-            .map_err(|err: JobError| err.as_failed_job(report, None))?;
+            .map_err(|err: JobError| err.into_failed_job(report, None))?;
 
         match RunningJob::new(
             child,
             self.step.clone(),
             progress,
-            progress_style,
-            prefer_warnings,
-            inspect,
+            settings.progress_style,
+            settings.prefer_warnings,
+            settings.inspect,
+            settings.rerun_trigger,
         ) {
             Ok(job) => Ok(job.into()),
-            Err(err) => Err(err.as_failed_job(
+            Err(err) => Err(err.into_failed_job(
                 JobReport {
                     warnings: Vec::new(),
                     step: self.step,
+                    execution: None,
                 },
                 None,
             )),
         }
     }
 
+    // the log directory has to exist before submission, since slurm does not create it
+    pub fn submit(&self, dependencies: &[SlurmJobID]) -> Result<SlurmJobID, JobError> {
+        std::fs::create_dir_all(
+            self.step
+                .log
+                .parent()
+                .expect("expected log to be validated as a file path"),
+        )
+        .map_err(|err| {
+            JobError::LogFileParentDirectoryCreation(self.step.log.clone(), err.into())
+        })?;
+
+        self.command.submit(
+            &ResourceGrant::unaccounted(&self.step.resources),
+            dependencies,
+        )
+    }
+
+    // records the run of a job which was submitted by an earlier invocation, once it finished
+    pub fn record_submitted_run(
+        &self,
+        submission_time: SystemTime,
+        status: RunStatus,
+        execution: ExecutionRecord,
+        rerun_trigger: RerunTrigger,
+    ) -> Result<(), JobError> {
+        let with_checksums = rerun_trigger == RerunTrigger::Checksum;
+        let input_fingerprints = read_fingerprints(&self.step.inputs, with_checksums)?;
+
+        record_output_metadata(
+            &self.step,
+            input_fingerprints,
+            submission_time,
+            status,
+            execution,
+            rerun_trigger,
+        )
+    }
+
+    // marks the outputs as up to date by updating their modification times and recording a
+    // successful run, which only makes sense if all outputs exist
+    fn touch(
+        self,
+        progress: &MultiProgress,
+        rerun_trigger: RerunTrigger,
+    ) -> Result<SuccessfulJob, FailedJob> {
+        let start_time = SystemTime::now();
+        let with_checksums = rerun_trigger == RerunTrigger::Checksum;
+        let input_fingerprints = read_fingerprints(&self.step.inputs, with_checksums)
+            .map_err(|err| JobError::from(err).into_failed_job(self.report(), None))?;
+
+        for output in self.step.outputs.iter() {
+            File::open(output)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .map_err(|err| {
+                    JobError::OutputTouch(output.clone(), err.into())
+                        .into_failed_job(self.report(), None)
+                })?;
+            let _ = progress.println(format!("touched `{output}`"));
+        }
+
+        record_output_metadata(
+            &self.step,
+            input_fingerprints,
+            start_time,
+            RunStatus::Successful,
+            ExecutionRecord::Touch,
+            rerun_trigger,
+        )
+        .map_err(|err| err.into_failed_job(self.report(), None))?;
+
+        Ok(SuccessfulJob::new(
+            JobReport {
+                warnings: Vec::new(),
+                step: self.step,
+                execution: None,
+            },
+            None,
+        ))
+    }
+
     fn report(&self) -> JobReport {
         JobReport {
             warnings: Vec::new(),
             step: self.step.clone(),
+            execution: None,
         }
     }
 }
 
+fn record_output_metadata(
+    step: &StepInfo,
+    input_fingerprints: BTreeMap<PathBuf, FileFingerprint>,
+    start_time: SystemTime,
+    status: RunStatus,
+    execution: ExecutionRecord,
+    rerun_trigger: RerunTrigger,
+) -> Result<(), JobError> {
+    let end_time = SystemTime::now();
+    // outputs of failed runs are not fingerprinted, since they are rerun anyway
+    let mut output_fingerprints = match status {
+        RunStatus::Successful => {
+            read_fingerprints(&step.outputs, rerun_trigger == RerunTrigger::Checksum)?
+        }
+        RunStatus::Failed { .. } => BTreeMap::new(),
+    };
+
+    for output in step.outputs.iter() {
+        write_output_metadata(
+            output,
+            &OutputMetadata {
+                step: step.name.clone(),
+                runner: step.runner.clone(),
+                inputs: input_fingerprints.clone(),
+                fingerprint: output_fingerprints.remove(output),
+                start_time,
+                end_time,
+                status: status.clone(),
+                execution: execution.clone(),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+// how a pending job is executed, which is decided by the graph execution
+pub struct JobExecutionSettings {
+    pub progress_style: JobProgressStyle,
+    pub prefer_warnings: bool,
+    pub inspect: bool,
+    pub rerun_trigger: RerunTrigger,
+    pub touch: bool,
+}
+
 pub struct JobProgressStyle {
     pub bar_style: ProgressStyle,
     pub spinner_style: ProgressStyle,
@@ -315,73 +521,68 @@ impl ProgressHandler {
     }
 }
 
+const INSPECTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const SPINNER_TICK_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
 struct JobOutputInspector {
     stop: Arc<AtomicBool>,
-    stdout_handle: JoinHandle<Result<(), JobError>>,
-    stderr_handle: JoinHandle<Result<(), JobError>>,
+    handle: JoinHandle<Result<(), JobError>>,
 }
 impl JobOutputInspector {
-    fn new<P: Into<PathBuf>>(child: &JobExecutionChild, progress: &MultiProgress, log: P) -> Self {
-        let stdout = BufReader::new(
-            child
-                .stdout
-                .take()
-                .expect("stdout exists when inspect is true"),
-        );
-        let stderr = BufReader::new(
-            child
-                .stderr
-                .take()
-                .expect("stderr exists when inspect is true"),
-        );
-
+    fn new<P: Into<PathBuf>>(progress: &MultiProgress, log: P) -> Self {
         let stop = Arc::new(AtomicBool::new(false));
-        let log = log.into();
         Self {
-            stdout_handle: JobOutputInspector::write(stdout, progress, log.clone(), &stop),
-            stderr_handle: JobOutputInspector::write(stderr, progress, log, &stop),
+            handle: JobOutputInspector::follow(progress, log.into(), &stop),
             stop,
         }
     }
 
-    fn write<R: io::Read + Send + 'static>(
-        reader: BufReader<R>,
+    // follow the job log like `tail -f` does, since the job output is not necessarily produced
+    // by a local process (e.g. for slurm jobs)
+    fn follow(
         progress: &MultiProgress,
         log: PathBuf,
         stop: &Arc<AtomicBool>,
     ) -> JoinHandle<Result<(), JobError>> {
         let progress = progress.clone();
-        let stop_stdout = stop.clone();
+        let stop = stop.clone();
         thread::spawn(move || -> Result<_, JobError> {
-            for line in reader.lines() {
-                if stop_stdout.load(Ordering::Acquire) {
-                    break;
+            let mut reader = loop {
+                match File::open(&log) {
+                    Ok(file) => break BufReader::new(file),
+                    Err(_) if !stop.load(Ordering::Acquire) => {
+                        thread::sleep(INSPECTION_POLL_INTERVAL)
+                    }
+                    Err(err) => return Err(JobError::InspectionOutputRead(err.into())),
+                }
+            };
+
+            let mut line = String::new();
+            loop {
+                // check before reading, so the log is drained once after stopping
+                let stopping = stop.load(Ordering::Acquire);
+                let byte_count = reader
+                    .read_line(&mut line)
+                    .map_err(|err| JobError::InspectionOutputRead(err.into()))?;
+
+                if line.ends_with('\n') || (byte_count == 0 && stopping && !line.is_empty()) {
+                    progress
+                        .println(line.trim_end_matches('\n'))
+                        .map_err(|err| JobError::InspectionOutputPrint(err.into()))?;
+                    line.clear();
+                } else if byte_count == 0 && stopping {
+                    return Ok(());
+                } else if byte_count == 0 {
+                    thread::sleep(INSPECTION_POLL_INTERVAL);
                 }
-
-                let line = line.map_err(|err| JobError::InspectionOutputRead(err.into()))?;
-                progress
-                    .println(&line)
-                    .map_err(|err| JobError::InspectionOutputPrint(err.into()))?;
-
-                OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&log)
-                    .map_err(|err| JobError::InspectionOutputWriteToLog(err.into()))?
-                    .write_all(format!("{line}\n").as_bytes())
-                    .map_err(|err| JobError::InspectionOutputWriteToLog(err.into()))?;
             }
-            Ok(())
         })
     }
 
     fn join(self) -> Result<(), JobError> {
-        self.stop.store(true, Ordering::Relaxed);
-        self.stdout_handle.join_or_panic()?;
-        self.stderr_handle.join_or_panic()?;
-
-        Ok(())
+        self.stop.store(true, Ordering::Release);
+        self.handle.join_or_panic()
     }
 }
 
@@ -392,6 +593,42 @@ pub struct RunningJob {
     error_catcher: ErrorCatcher,
     step: StepInfo,
     output_inspector: Option<JobOutputInspector>,
+    start_time: SystemTime,
+    input_fingerprints: InputFingerprints,
+    rerun_trigger: RerunTrigger,
+}
+
+// inputs are fingerprinted in the background, since checksums of large inputs would otherwise
+// hold up the scheduling of all other jobs
+#[derive(Debug)]
+enum InputFingerprints {
+    Reading(Receiver<Result<BTreeMap<PathBuf, FileFingerprint>, MetadataError>>),
+    Read(Result<BTreeMap<PathBuf, FileFingerprint>, MetadataError>),
+}
+impl InputFingerprints {
+    fn read(paths: Vec<PathBuf>, with_checksums: bool) -> Self {
+        let (sender, receiver) = channel();
+        rayon::spawn(move || {
+            // the job might be gone already, in which case nobody is interested anymore
+            let _ = sender.send(read_fingerprints(&paths, with_checksums));
+        });
+        Self::Reading(receiver)
+    }
+
+    fn join(&mut self) -> Result<BTreeMap<PathBuf, FileFingerprint>, MetadataError> {
+        if let Self::Reading(receiver) = self {
+            *self = Self::Read(
+                receiver
+                    .recv()
+                    .expect("expected fingerprinting to always send its result"),
+            );
+        }
+
+        match self {
+            Self::Read(result) => result.clone(),
+            Self::Reading(_) => unreachable!("fingerprints were joined above"),
+        }
+    }
 }
 
 impl RunningJob {
@@ -402,15 +639,20 @@ impl RunningJob {
         progress_style: JobProgressStyle,
         prefer_warnings: bool,
         inspect: bool,
+        rerun_trigger: RerunTrigger,
     ) -> Result<Self, JobError> {
+        let start_time = SystemTime::now();
         let mut error_catcher = ErrorCatcher::new(!prefer_warnings);
 
+        let input_fingerprints =
+            InputFingerprints::read(step.inputs.clone(), rerun_trigger == RerunTrigger::Checksum);
+
         let progress_scanner = step
             .progress_scanning
             .as_ref()
-            .map(|info| ProgressScanner::new(&info))
+            .map(ProgressScanner::new)
             .transpose()
-            .map_err(|err| JobError::ProgressScanSetup(err))
+            .map_err(JobError::ProgressScanSetup)
             .try_catch(&mut error_catcher)?
             .unwrap_or(None);
 
@@ -421,13 +663,22 @@ impl RunningJob {
             progress_style,
         );
         progress_handler.bar = progress.add(progress_handler.bar);
+        // spinners keep moving on their own, independent of how often progress is refreshed
+        if progress_handler.scanner.is_none() {
+            progress_handler
+                .bar
+                .enable_steady_tick(SPINNER_TICK_INTERVAL);
+        }
 
         Ok(Self {
-            output_inspector: inspect.then(|| JobOutputInspector::new(&child, progress, &step.log)),
+            output_inspector: inspect.then(|| JobOutputInspector::new(progress, &step.log)),
             child,
             progress: progress_handler,
             step,
             error_catcher,
+            start_time,
+            input_fingerprints,
+            rerun_trigger,
         })
     }
 
@@ -448,75 +699,126 @@ impl RunningJob {
         Ok(())
     }
 
-    pub fn done(&mut self) -> Result<bool, FailedJob> {
-        let result = self
-            .child
-            .borrow_mut()
-            .try_wait()
-            .map_err(|err| {
-                JobError::Wait(format!("{:?}", self.command), err.into())
-                    .as_failed_job(self.report(), Some(self.progress.bar.clone()))
-            })
-            .map(|status| status.is_some());
+    pub fn exit_notification(&self) -> ExitNotification<'_> {
+        self.child.exit_notification()
+    }
 
-        if result.is_err() {
-            // we only care about the first error
-            let _ = self.cleanup_fail();
+    pub fn done(&mut self) -> Result<bool, FailedJob> {
+        let result = self.child.try_wait();
+        if let Some(warning) = self.child.take_warning() {
+            self.progress.bar.println(format!("warning: {warning}"));
         }
+        let err = match result {
+            Ok(done) => return Ok(done),
+            Err(err) => err,
+        };
 
-        return result;
+        // the job might still be running (e.g. a slurm job which could not be polled), so it is
+        // killed and its outputs are marked as failed, like those of terminated jobs; we only
+        // care about the first error
+        let error = JobError::JobExecution(err);
+        let _ = self.child.kill();
+        let _ = self.record_metadata(RunStatus::Failed {
+            error: error.to_string(),
+        });
+        let _ = self.cleanup_fail();
+
+        return Err(error.into_failed_job(self.report(), Some(self.progress.bar.clone())));
     }
 
     pub fn finish(mut self) -> Result<SuccessfulJob, FailedJob> {
-        let exit_status = self.child.borrow_mut().wait().map_err(|err| {
-            JobError::Wait(format!("{:?}", &self.command), err.into())
-                .as_failed_job(self.report(), Some(self.progress.bar.clone()))
-        })?;
-
-        match exit_status.code() {
-            Some(0) => {
-                self.cleanup_success()
-                    .try_catch(&mut self.error_catcher)
-                    .map_err(|err| {
-                        FailedJob::new(err, self.report(), Some(self.progress.bar.clone()))
-                    })?;
-                Ok(SuccessfulJob::new(
-                    JobReport {
-                        warnings: self.error_catcher.warnings,
-                        step: self.step,
-                    },
-                    Some(self.progress.bar),
-                ))
-            }
-            Some(code) => {
-                // we only care about the first error
-                let _ = self.cleanup_fail();
-                Err(
-                    JobError::NonZeroExitCode(format!("{:?}", self.command), code)
-                        .as_failed_job(self.report(), Some(self.progress.bar)),
-                )
-            }
-            None => {
-                // we only care about the first error
-                let _ = self.cleanup_fail();
-                Err(JobError::SignalTermination(format!("{:?}", self.command))
-                    .as_failed_job(self.report(), Some(self.progress.bar)))
+        if let Err(err) = self.child.wait() {
+            let error = err.job_error().unwrap_or(JobError::JobExecution(err));
+            // the job would have never started, which is most likely a mistake in the options
+            if let JobError::SlurmUnresolvablePending { .. } = error {
+                self.progress.bar.println(format!(
+                    "warning: `{name}` failed: {error}",
+                    name = self.step.name
+                ));
             }
+            // we only care about the first error
+            let _ = self.cleanup_fail();
+            let _ = self.record_metadata(RunStatus::Failed {
+                error: error.to_string(),
+            });
+            return Err(error.into_failed_job(self.report(), Some(self.progress.bar)));
         }
+
+        self.cleanup_success()
+            .try_catch(&mut self.error_catcher)
+            .map_err(|err| FailedJob::new(err, self.report(), Some(self.progress.bar.clone())))?;
+        self.record_metadata(RunStatus::Successful)
+            .try_catch(&mut self.error_catcher)
+            .map_err(|err| FailedJob::new(err, self.report(), Some(self.progress.bar.clone())))?;
+        Ok(SuccessfulJob::new(
+            JobReport {
+                warnings: self.error_catcher.warnings,
+                step: self.step,
+                execution: self.child.record(),
+            },
+            Some(self.progress.bar),
+        ))
     }
 
-    pub fn terminate(mut self) -> Result<TerminatedJob, FailedJob> {
-        let result = match self.child.borrow_mut().kill() {
-            Ok(()) => Ok(TerminatedJob::new(
-                JobReport {
-                    warnings: self.error_catcher.warnings.clone(),
-                    step: self.step.clone(),
-                },
-                Some(self.progress.bar.clone()),
-            )),
-            Err(err) => Err(JobError::Kill(format!("{:?}", &self.command), err.into())
-                .as_failed_job(self.report(), Some(self.progress.bar.clone()))),
+    // jobs which never started did not touch their outputs
+    fn record_metadata(&mut self, status: RunStatus) -> Result<(), JobError> {
+        let Some(execution) = self.child.record() else {
+            return Ok(());
         };
+        let input_fingerprints = self
+            .input_fingerprints
+            .join()
+            .map_err(JobError::from)
+            .try_catch(&mut self.error_catcher)?
+            .unwrap_or_default();
+
+        record_output_metadata(
+            &self.step,
+            input_fingerprints,
+            self.start_time,
+            status,
+            execution,
+            self.rerun_trigger,
+        )
+    }
+
+    // submits all jobs at once, which lets executors batch the submission (e.g. slurm), failed
+    // submissions fail the jobs once they are polled
+    pub fn submit_all(jobs: Vec<&mut RunningJob>) {
+        submit_children(jobs.into_iter().map(|job| &mut job.child).collect())
+    }
+
+    // kills all jobs at once, which lets executors batch the cancellation (e.g. slurm)
+    pub fn terminate_all(mut jobs: Vec<RunningJob>) -> Vec<Result<TerminatedJob, FailedJob>> {
+        let kill_results = kill_children(jobs.iter_mut().map(|job| &mut job.child).collect());
+        jobs.into_iter()
+            .zip(kill_results)
+            .map(|(job, kill_result)| job.terminated(kill_result))
+            .collect()
+    }
+
+    fn terminated(
+        mut self,
+        kill_result: Result<(), JobExecutionError>,
+    ) -> Result<TerminatedJob, FailedJob> {
+        // outputs of terminated jobs might be incomplete
+        let _ = self.record_metadata(RunStatus::Failed {
+            error: "terminated".to_owned(),
+        });
+
+        let result =
+            match kill_result {
+                Ok(()) => Ok(TerminatedJob::new(
+                    JobReport {
+                        warnings: self.error_catcher.warnings.clone(),
+                        step: self.step.clone(),
+                        execution: self.child.record(),
+                    },
+                    Some(self.progress.bar.clone()),
+                )),
+                Err(err) => Err(JobError::Kill(err)
+                    .into_failed_job(self.report(), Some(self.progress.bar.clone()))),
+            };
 
         if result.is_ok() {
             self.cleanup_success()
@@ -531,6 +833,10 @@ impl RunningJob {
     }
 
     pub fn update_progress(mut self) -> Result<RunningJob, FailedJob> {
+        self.progress.bar.set_message(match self.child.status() {
+            Some(status) => format!("{name} ({status})", name = self.step.name),
+            None => self.step.name.clone(),
+        });
         self.progress
             .update(&self.step.log)
             .try_catch(&mut self.error_catcher)
@@ -546,14 +852,23 @@ impl RunningJob {
         JobReport {
             warnings: self.error_catcher.warnings.clone(),
             step: self.step.clone(),
+            execution: self.child.record(),
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct JobReport {
+    #[allow(unused)]
     warnings: Vec<JobError>,
     step: StepInfo,
+    // only jobs which were already executing have an execution record
+    execution: Option<ExecutionRecord>,
+}
+impl JobReport {
+    pub fn execution(&self) -> Option<&ExecutionRecord> {
+        self.execution.as_ref()
+    }
 }
 
 #[derive(Clone, Debug, thiserror::Error, Diagnostic)]
@@ -564,15 +879,16 @@ pub struct JobReport {
 )]
 #[diagnostic(help("check {log} or execute nixflow with `--inspect {name}` (if not done so already) to inspect the job output", name = report.step.name, log = report.step.log))]
 pub struct FailedJob {
-    error: JobError,
-    report: JobReport,
+    // boxed to keep results which fail with this job small
+    error: Box<JobError>,
+    report: Box<JobReport>,
     progress: Option<ProgressBar>,
 }
 impl FailedJob {
     pub fn new(error: JobError, report: JobReport, progress: Option<ProgressBar>) -> Self {
         Self {
-            error,
-            report,
+            error: Box::new(error),
+            report: Box::new(report),
             progress,
         }
     }
@@ -613,27 +929,27 @@ impl TerminatedJob {
 }
 
 pub enum ExecutedJob {
-    Running(RunningJob),
-    Finished(SuccessfulJob),
+    Running(Box<RunningJob>),
+    Finished(Box<SuccessfulJob>),
 }
 impl From<SuccessfulJob> for ExecutedJob {
     fn from(successful: SuccessfulJob) -> Self {
-        ExecutedJob::Finished(successful)
+        ExecutedJob::Finished(Box::new(successful))
     }
 }
 impl From<RunningJob> for ExecutedJob {
     fn from(running: RunningJob) -> Self {
-        ExecutedJob::Running(running)
+        ExecutedJob::Running(Box::new(running))
     }
 }
 
 pub enum FinishedJob {
-    Successful(SuccessfulJob),
+    Successful(Box<SuccessfulJob>),
     Failed(FailedJob),
 }
 impl From<SuccessfulJob> for FinishedJob {
     fn from(successful: SuccessfulJob) -> Self {
-        FinishedJob::Successful(successful)
+        FinishedJob::Successful(Box::new(successful))
     }
 }
 impl From<FailedJob> for FinishedJob {
@@ -659,21 +975,46 @@ pub enum JobError {
     #[error("failed to check for the existence of {0}\n{1}")]
     OutputExistenceCheck(PathBuf, IoError),
 
-    #[error("failed to create the parent directory for the specified log file `{0}`\n{1}")]
-    LogFileParentDirectoryCreation(PathBuf, IoError),
+    #[error("failed to read the modification time of `{0}`\n{1}")]
+    ModificationTimeRead(PathBuf, IoError),
 
-    #[error("failed to poll `{0}`\n{1}")]
-    Wait(String, IoError),
+    #[error("failed to access the run metadata\n{0}")]
+    Metadata(
+        #[source]
+        #[from]
+        MetadataError,
+    ),
 
-    #[allow(unused)]
-    #[error("failed to kill `{0}`\n{1}")]
-    Kill(String, IoError),
+    #[error(
+        "the job requests {requested} of the resource `{name}`, but only {available} are available"
+    )]
+    UnsatisfiableResource {
+        name: String,
+        requested: u64,
+        available: u64,
+    },
+
+    #[error("failed to update the modification time of `{0}`\n{1}")]
+    OutputTouch(PathBuf, IoError),
+
+    #[error("the `{0}` executor does not support submitting jobs without waiting for them")]
+    UnsupportedSubmission(ExecutionMethod),
 
-    #[error("failed to execute `{0}`, terminated by a signal")]
-    SignalTermination(String),
+    #[error(
+        "only slurm jobs can be submitted without waiting for them, but the following steps use \
+        other executors:\n\t{}",
+        steps.join("\n\t")
+    )]
+    UnsubmittableSteps { steps: Vec<String> },
+
+    #[error("failed to submit\n{0}")]
+    Submission(JobExecutionError),
+
+    #[error("failed to create the parent directory for the specified log file `{0}`\n{1}")]
+    LogFileParentDirectoryCreation(PathBuf, IoError),
 
-    #[error("failed to execute `{0}`, exit code {1} is non-zero")]
-    NonZeroExitCode(String, i32),
+    #[error("failed to terminate\n{0}")]
+    Kill(JobExecutionError),
 
     #[error("failed to read progress from `{0}`\n{1}")]
     ProgressLogRead(PathBuf, IoError),
@@ -686,7 +1027,7 @@ pub enum JobError {
 
     #[error(
         "one or more parent jobs failed:\n\t{}",
-        parents.into_iter().map(|step| step.name.as_str()).collect::<Vec<_>>().join("\n\t"))
+        parents.iter().map(|step| step.name.as_str()).collect::<Vec<_>>().join("\n\t"))
     ]
     ParentsFailed { parents: Vec<StepInfo> },
 
@@ -696,8 +1037,49 @@ pub enum JobError {
     #[error("failed to print a line to stdout during job output inspection")]
     InspectionOutputPrint(#[source] IoError),
 
-    #[error("failed to write a line to the job log during job output inspection")]
-    InspectionOutputWriteToLog(#[source] IoError),
+    #[error("slurm job {job_id} exceeded its time limit after running for {elapsed}")]
+    SlurmTimeout { job_id: SlurmJobID, elapsed: String },
+
+    #[error("slurm job {job_id} ran out of memory (maximum resident set size: {max_rss})")]
+    SlurmOutOfMemory { job_id: SlurmJobID, max_rss: String },
+
+    #[error("slurm job {job_id} failed because of a failure of the node(s) `{nodes}`")]
+    SlurmNodeFailure { job_id: SlurmJobID, nodes: String },
+
+    #[error("slurm job {job_id} was cancelled, since it would be pending forever due to {reason}")]
+    SlurmUnresolvablePending { job_id: SlurmJobID, reason: String },
+
+    #[error("slurm job {job_id} was cancelled")]
+    SlurmCancellation { job_id: SlurmJobID },
+
+    #[error("slurm job {job_id} was preempted")]
+    SlurmPreemption { job_id: SlurmJobID },
+
+    #[error("slurm job {job_id} was terminated by signal {signal}")]
+    SlurmSignalTermination { job_id: SlurmJobID, signal: i32 },
+
+    #[error(
+        "the job exited with non-zero exit code {code} within the slurm group allocation {job_id}"
+    )]
+    SlurmGroupMemberFailure { job_id: SlurmJobID, code: i32 },
+
+    #[error(
+        "one or more parent jobs failed within the slurm group allocation {job_id}, so the job \
+        did not run"
+    )]
+    SlurmGroupParentFailure { job_id: SlurmJobID },
+
+    #[error("slurm job {job_id} exited with non-zero exit code {code} on `{nodes}`")]
+    SlurmNonZeroExitCode {
+        job_id: SlurmJobID,
+        code: i32,
+        nodes: String,
+    },
+
+    #[error(
+        "`{0}` lies outside of the working directory, so it can not be staged in scratch space"
+    )]
+    ScratchStagingOutsideWorkdir(PathBuf),
 
     #[error("failed to execute\n{0}")]
     JobExecution(
@@ -707,12 +1089,12 @@ pub enum JobError {
     ),
 }
 
-pub trait AsFailedJob {
-    fn as_failed_job(self, report: JobReport, progress: Option<ProgressBar>) -> FailedJob;
+pub trait IntoFailedJob {
+    fn into_failed_job(self, report: JobReport, progress: Option<ProgressBar>) -> FailedJob;
 }
 
-impl AsFailedJob for JobError {
-    fn as_failed_job(self, report: JobReport, progress: Option<ProgressBar>) -> FailedJob {
+impl IntoFailedJob for JobError {
+    fn into_failed_job(self, report: JobReport, progress: Option<ProgressBar>) -> FailedJob {
         FailedJob::new(self, report, progress)
     }
 }
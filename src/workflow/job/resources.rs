@@ -0,0 +1,145 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use super::JobError;
+
+// the number of threads granted to a job is exported to it in this variable
+pub const THREADS_ENVIRONMENT_VARIABLE: &str = "NIXFLOW_THREADS";
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StepResources {
+    #[serde(default = "default_threads")]
+    pub threads: u32,
+
+    // named counters like `mem_mb`, `gpu` or `db_connections`
+    #[serde(flatten)]
+    pub counters: BTreeMap<String, u64>,
+}
+impl Default for StepResources {
+    fn default() -> Self {
+        Self {
+            threads: default_threads(),
+            counters: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_threads() -> u32 {
+    1
+}
+
+// counters which are not limited are considered to be unlimited
+#[derive(Clone, Debug)]
+pub struct ResourceLimits {
+    pub cores: u32,
+    pub counters: BTreeMap<String, u64>,
+}
+
+pub fn parse_resource_limit(limit: &str) -> Result<(String, u64), String> {
+    let (name, amount) = limit
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name=amount`, got `{limit}`"))?;
+    let amount = amount
+        .parse()
+        .map_err(|err| format!("invalid amount `{amount}` for `{name}`: {err}"))?;
+
+    Ok((name.to_owned(), amount))
+}
+
+#[derive(Clone, Debug)]
+pub struct ResourceGrant {
+    pub threads: u32,
+    counters: BTreeMap<String, u64>,
+    accounted: bool,
+}
+impl ResourceGrant {
+    // for jobs which do not run on the local machine and therefore do not use up local resources
+    pub fn unaccounted(resources: &StepResources) -> Self {
+        Self {
+            threads: resources.threads,
+            counters: BTreeMap::new(),
+            accounted: false,
+        }
+    }
+}
+
+// keeps track of the resources used by the currently running local jobs
+#[derive(Debug)]
+pub struct ResourcePool {
+    limits: ResourceLimits,
+    used_cores: u32,
+    used_counters: BTreeMap<String, u64>,
+}
+impl ResourcePool {
+    pub fn new(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            used_cores: 0,
+            used_counters: BTreeMap::new(),
+        }
+    }
+
+    // jobs requesting more threads than there are cores are scaled down instead, while jobs
+    // requesting more of a counter than available could never run
+    pub fn ensure_satisfiable(&self, resources: &StepResources) -> Result<(), JobError> {
+        for (name, &requested) in resources.counters.iter() {
+            if let Some(&available) = self.limits.counters.get(name)
+                && requested > available
+            {
+                return Err(JobError::UnsatisfiableResource {
+                    name: name.clone(),
+                    requested,
+                    available,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn try_acquire(&mut self, resources: &StepResources) -> Option<ResourceGrant> {
+        let threads = resources.threads.min(self.limits.cores);
+        if self.used_cores + threads > self.limits.cores {
+            return None;
+        }
+
+        let counters = resources
+            .counters
+            .iter()
+            .filter(|(name, _)| self.limits.counters.contains_key(*name))
+            .map(|(name, &requested)| (name.clone(), requested))
+            .collect::<BTreeMap<_, _>>();
+        let fits = counters.iter().all(|(name, requested)| {
+            self.used_counters.get(name).copied().unwrap_or(0) + requested
+                <= self.limits.counters[name]
+        });
+        if !fits {
+            return None;
+        }
+
+        self.used_cores += threads;
+        for (name, requested) in counters.iter() {
+            *self.used_counters.entry(name.clone()).or_insert(0) += requested;
+        }
+
+        Some(ResourceGrant {
+            threads,
+            counters,
+            accounted: true,
+        })
+    }
+
+    pub fn release(&mut self, grant: &ResourceGrant) {
+        if !grant.accounted {
+            return;
+        }
+
+        self.used_cores -= grant.threads;
+        for (name, amount) in grant.counters.iter() {
+            *self
+                .used_counters
+                .get_mut(name)
+                .expect("granted counters are always in use") -= amount;
+        }
+    }
+}
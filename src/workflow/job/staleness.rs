@@ -0,0 +1,186 @@
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use clap::ValueEnum;
+use derive_more::Display;
+use rayon::prelude::*;
+use std::time::SystemTime;
+
+use crate::workflow::metadata::{FileFingerprint, RunStatus, read_output_metadata};
+
+use super::JobError;
+
+#[derive(Display, Default, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RerunTrigger {
+    #[default]
+    #[display("mtime")]
+    #[value(name = "mtime")]
+    ModificationTime,
+    #[display("checksum")]
+    Checksum,
+}
+
+// steps which are rerun regardless of the state of their outputs
+#[derive(Clone, Debug, Default)]
+pub enum ForcedRerun {
+    #[default]
+    None,
+    Steps(Vec<String>),
+    All,
+}
+impl ForcedRerun {
+    pub fn new(steps: Vec<String>, all: bool) -> Self {
+        if all {
+            Self::All
+        } else if !steps.is_empty() {
+            Self::Steps(steps)
+        } else {
+            Self::None
+        }
+    }
+
+    pub fn includes(&self, step: &str) -> bool {
+        match self {
+            Self::None => false,
+            Self::Steps(steps) => steps.iter().any(|forced| forced == step),
+            Self::All => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+pub enum RerunReason {
+    #[display("missing output `{_0}`")]
+    MissingOutput(PathBuf),
+    #[display("output `{_0}` is incomplete, since its last run failed")]
+    IncompleteOutput(PathBuf),
+    #[display("runner of `{output}` changed from `{previous_runner}`")]
+    ChangedRunner {
+        output: PathBuf,
+        previous_runner: PathBuf,
+    },
+    #[display("input `{input}` is newer than output `{output}`")]
+    UpdatedInput { input: PathBuf, output: PathBuf },
+    #[display("content of input `{input}` changed since `{output}` was produced")]
+    ChangedInput { input: PathBuf, output: PathBuf },
+    #[display("parent `{_0}` is rerun")]
+    RerunParent(String),
+    #[display("rerun is forced")]
+    Forced,
+}
+
+fn modification_time(path: &Path) -> Result<SystemTime, JobError> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| JobError::ModificationTimeRead(path.to_owned(), err.into()))
+}
+
+// expects all outputs to exist, inputs which do not exist are ignored since they are produced by
+// a parent that is rerun anyway
+pub fn updated_input_reason(
+    inputs: &[PathBuf],
+    outputs: &[PathBuf],
+) -> Result<Option<RerunReason>, JobError> {
+    let oldest_output = outputs
+        .iter()
+        .map(|output| modification_time(output).map(|time| (output, time)))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .min_by_key(|(_, time)| *time);
+    let Some((oldest_output, oldest_output_time)) = oldest_output else {
+        return Ok(None);
+    };
+
+    let newest_input = inputs
+        .iter()
+        .filter(|input| input.exists())
+        .map(|input| modification_time(input).map(|time| (input, time)))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .max_by_key(|(_, time)| *time);
+
+    Ok(newest_input
+        .filter(|(_, newest_input_time)| *newest_input_time > oldest_output_time)
+        .map(|(newest_input, _)| RerunReason::UpdatedInput {
+            input: newest_input.clone(),
+            output: oldest_output.clone(),
+        }))
+}
+
+// outputs without metadata were not produced by nixflow (or by an older version of it), which is
+// why they are not considered as changed
+pub fn recorded_run_reason(
+    runner: &Path,
+    outputs: &[PathBuf],
+) -> Result<Option<RerunReason>, JobError> {
+    for output in outputs {
+        let Some(metadata) = read_output_metadata(output)? else {
+            continue;
+        };
+
+        if let RunStatus::Failed { .. } = metadata.status {
+            return Ok(Some(RerunReason::IncompleteOutput(output.clone())));
+        }
+
+        if metadata.runner != runner {
+            return Ok(Some(RerunReason::ChangedRunner {
+                output: output.clone(),
+                previous_runner: metadata.runner,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+// outputs without checksums recorded for all of their inputs fall back to comparing modification
+// times, which is the case for outputs produced before switching to checksums
+pub fn changed_input_reason(
+    inputs: &[PathBuf],
+    outputs: &[PathBuf],
+) -> Result<Option<RerunReason>, JobError> {
+    let mut recorded_inputs = Vec::new();
+    for output in outputs {
+        let recorded = read_output_metadata(output)?.map(|metadata| metadata.inputs);
+        let Some(recorded) = recorded.filter(|recorded| {
+            inputs.iter().all(|input| {
+                recorded
+                    .get(input)
+                    .is_some_and(|print| print.checksum.is_some())
+            })
+        }) else {
+            return updated_input_reason(inputs, outputs);
+        };
+        recorded_inputs.push((output, recorded));
+    }
+
+    // inputs which were not touched since the last run are unchanged without computing checksums
+    let possibly_changed_inputs = inputs
+        .par_iter()
+        .filter(|input| input.exists())
+        .map(|input| {
+            let current = FileFingerprint::read(input, false)?;
+            let unchanged = recorded_inputs
+                .iter()
+                .all(|(_, recorded)| recorded[input].unchanged(&current));
+            Ok((!unchanged).then_some(input))
+        })
+        .filter_map(|result: Result<_, JobError>| result.transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let current_checksums = possibly_changed_inputs
+        .par_iter()
+        .map(|input| FileFingerprint::read(input, true).map(|print| (*input, print.checksum)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (output, recorded) in recorded_inputs {
+        for (input, checksum) in current_checksums.iter() {
+            if recorded[*input].checksum != *checksum {
+                return Ok(Some(RerunReason::ChangedInput {
+                    input: (*input).clone(),
+                    output: output.clone(),
+                }));
+            }
+        }
+    }
+
+    Ok(None)
+}
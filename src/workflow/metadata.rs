@@ -0,0 +1,189 @@
+use camino::{Utf8Path as Path, Utf8PathBuf as PathBuf};
+use derive_more::Display;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io,
+    time::{Duration, SystemTime},
+};
+
+use crate::utils::IoError;
+
+use super::job::execution::ExecutionRecord;
+
+const METADATA_DIRECTORY: &str = ".nixflow/metadata";
+
+// everything we know about the run which produced an output, recorded once the run finished
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OutputMetadata {
+    pub step: String,
+    pub runner: PathBuf,
+    pub inputs: BTreeMap<PathBuf, FileFingerprint>,
+    pub fingerprint: Option<FileFingerprint>,
+    pub start_time: SystemTime,
+    pub end_time: SystemTime,
+    pub status: RunStatus,
+    pub execution: ExecutionRecord,
+}
+impl OutputMetadata {
+    pub fn runtime(&self) -> Duration {
+        self.end_time
+            .duration_since(self.start_time)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub modification_time: SystemTime,
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+impl FileFingerprint {
+    pub fn read(path: &Path, with_checksum: bool) -> Result<Self, MetadataError> {
+        let metadata = std::fs::metadata(path)
+            .map_err(|err| MetadataError::FingerprintRead(path.to_owned(), err.into()))?;
+        let mut fingerprint = Self {
+            size: metadata.len(),
+            modification_time: metadata
+                .modified()
+                .map_err(|err| MetadataError::FingerprintRead(path.to_owned(), err.into()))?,
+            checksum: None,
+        };
+        if !with_checksum {
+            return Ok(fingerprint);
+        }
+
+        // the checksum of an output of a previous run can be reused as long as the output was not
+        // touched since then
+        let cached_checksum = read_output_metadata(path)?
+            .and_then(|metadata| metadata.fingerprint)
+            .filter(|cached| cached.unchanged(&fingerprint))
+            .and_then(|cached| cached.checksum);
+        fingerprint.checksum = Some(match cached_checksum {
+            Some(checksum) => checksum,
+            None => file_checksum(path)?,
+        });
+
+        Ok(fingerprint)
+    }
+
+    pub fn unchanged(&self, other: &FileFingerprint) -> bool {
+        self.size == other.size && self.modification_time == other.modification_time
+    }
+}
+
+// large files are common, so all paths are fingerprinted in parallel
+pub fn read_fingerprints(
+    paths: &[PathBuf],
+    with_checksums: bool,
+) -> Result<BTreeMap<PathBuf, FileFingerprint>, MetadataError> {
+    paths
+        .par_iter()
+        .map(|path| FileFingerprint::read(path, with_checksums).map(|print| (path.clone(), print)))
+        .collect()
+}
+
+fn file_checksum(path: &Path) -> Result<String, MetadataError> {
+    let mut file =
+        File::open(path).map_err(|err| MetadataError::ChecksumRead(path.to_owned(), err.into()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .map_err(|err| MetadataError::ChecksumRead(path.to_owned(), err.into()))?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[derive(Clone, Debug, Display, Deserialize, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunStatus {
+    #[display("successful")]
+    Successful,
+    #[display("failed")]
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum MetadataError {
+    #[error("failed to create the metadata directory `{0}`\n{1}")]
+    DirectoryCreation(PathBuf, IoError),
+
+    #[error("failed to read the metadata file `{0}`\n{1}")]
+    Read(PathBuf, IoError),
+
+    #[error("failed to parse the metadata file `{0}`\n{1}")]
+    Parsing(PathBuf, String),
+
+    #[error("failed to write the metadata file `{0}`\n{1}")]
+    Write(PathBuf, IoError),
+
+    #[error("failed to read size and modification time of `{0}`\n{1}")]
+    FingerprintRead(PathBuf, IoError),
+
+    #[error("failed to remove the metadata file `{0}`\n{1}")]
+    Removal(PathBuf, IoError),
+
+    #[error("failed to compute the checksum of `{0}`\n{1}")]
+    ChecksumRead(PathBuf, IoError),
+}
+
+// one file per output, named after the percent encoded output path, so that outputs in different
+// directories never collide
+fn metadata_path(output: &Path) -> PathBuf {
+    let file_name: String = output
+        .as_str()
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'_' | b'-' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect();
+
+    PathBuf::from(METADATA_DIRECTORY).join(format!("{file_name}.json"))
+}
+
+pub fn read_output_metadata(output: &Path) -> Result<Option<OutputMetadata>, MetadataError> {
+    let path = metadata_path(output);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(MetadataError::Read(path, err.into())),
+    };
+
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|err| MetadataError::Parsing(path, err.to_string()))
+}
+
+pub fn write_output_metadata(
+    output: &Path,
+    metadata: &OutputMetadata,
+) -> Result<(), MetadataError> {
+    std::fs::create_dir_all(METADATA_DIRECTORY).map_err(|err| {
+        MetadataError::DirectoryCreation(PathBuf::from(METADATA_DIRECTORY), err.into())
+    })?;
+
+    let path = metadata_path(output);
+    let contents = serde_json::to_string_pretty(metadata)
+        .expect("expected serialization of output metadata to always succeed");
+    std::fs::write(&path, contents).map_err(|err| MetadataError::Write(path, err.into()))
+}
+
+pub fn remove_output_metadata(output: &Path) -> Result<(), MetadataError> {
+    let path = metadata_path(output);
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(MetadataError::Removal(path, err.into())),
+    }
+}
@@ -1,12 +1,17 @@
 use camino::Utf8Path as Path;
 use std::process::{Command, Stdio};
 
-use crate::nix_environment::{FlakeOutput, FlakeSource, NixEnvironment, NixRunCommandOptions};
+use crate::{
+    commands::CommandError,
+    nix_environment::{FlakeOutput, FlakeSource, NixEnvironment, NixRunCommandOptions},
+};
 
 pub mod graph;
+pub mod interrupt;
 pub mod job;
+pub mod metadata;
 pub mod specification;
-
+pub mod submission;
 
 #[derive(Debug, thiserror::Error)]
 pub enum WorkflowError {
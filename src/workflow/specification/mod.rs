@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
 use camino::Utf8PathBuf as PathBuf;
 use miette::{Context, IntoDiagnostic};
@@ -9,7 +9,7 @@ use serde_with::{OneOrMany, serde_as};
 
 use progress::ProgressScanningInfo;
 
-use super::job::execution::ExecutionOptions;
+use super::job::{execution::ExecutionOptions, resources::StepResources};
 
 mod parsing;
 pub mod progress;
@@ -57,35 +57,72 @@ pub struct Step {
     #[serde(default)]
     pub execution: ExecutionOptions,
 
+    #[serde(default)]
+    pub resources: StepResources,
+
+    // jobs of steps with higher priority are started first when more jobs are ready than can run
+    #[serde(default)]
+    pub priority: i32,
+
+    // connected slurm jobs of the same group are run within a single allocation
+    #[serde(default)]
+    pub group: Option<String>,
+
     pub log: PathBuf,
 
     #[serde(rename = "progress")]
     pub progress_scanning: Option<ProgressScanningInfo>,
 
     #[serde(rename = "run")]
-    #[allow(unused)]
     run_binary_path: PathBuf,
 }
 impl Step {
+    pub fn identity(&self) -> StepIdentity {
+        StepIdentity {
+            name: self.name.clone(),
+            outputs: self
+                .outputs
+                .values()
+                .flat_map(|output_list| {
+                    output_list.outputs.iter().map(|output| output.path.clone())
+                })
+                .collect(),
+        }
+    }
+
     pub fn info(&self) -> StepInfo {
-        StepInfo::new(
-            self.name.clone(),
-            self.inputs
+        StepInfo {
+            name: self.name.clone(),
+            inputs: self
+                .inputs
                 .values()
                 .flat_map(|input_list| input_list.inputs.iter().map(|input| input.path.clone()))
                 .collect(),
-            self.outputs
+            outputs: self
+                .outputs
                 .values()
                 .flat_map(|output_list| {
                     output_list.outputs.iter().map(|output| output.path.clone())
                 })
                 .collect(),
-            self.log.clone(),
-            self.progress_scanning.clone(),
-        )
+            log: self.log.clone(),
+            progress_scanning: self.progress_scanning.clone(),
+            runner: self.run_binary_path.clone(),
+            resources: self.resources.clone(),
+            priority: self.priority,
+            group: self.group.clone(),
+        }
     }
 }
 
+// steps are repeated in the specification for every consumer of their outputs, so the name
+// together with the produced outputs is what identifies a step across the workflow
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StepIdentity {
+    name: String,
+    outputs: BTreeSet<PathBuf>,
+}
+
 #[derive(Clone, Debug)]
 pub struct StepInfo {
     pub name: String,
@@ -93,6 +130,10 @@ pub struct StepInfo {
     pub outputs: Vec<PathBuf>,
     pub log: PathBuf,
     pub progress_scanning: Option<ProgressScanningInfo>,
+    pub runner: PathBuf,
+    pub resources: StepResources,
+    pub priority: i32,
+    pub group: Option<String>,
 }
 impl StepInfo {
     pub fn progress_max(&self) -> Option<u32> {
@@ -102,24 +143,6 @@ impl StepInfo {
     }
 }
 
-impl StepInfo {
-    pub fn new(
-        name: String,
-        inputs: Vec<PathBuf>,
-        outputs: Vec<PathBuf>,
-        log: PathBuf,
-        progress_scanning: Option<ProgressScanningInfo>,
-    ) -> Self {
-        Self {
-            name,
-            inputs,
-            outputs,
-            log,
-            progress_scanning,
-        }
-    }
-}
-
 impl From<&StepInfo> for StepInfo {
     fn from(value: &StepInfo) -> Self {
         value.clone()
@@ -143,7 +166,36 @@ pub struct WorkflowSpecification {
     pub targets: HashMap<String, Vec<TargetItem>>,
 }
 
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "the workflow specification contains no target named `{name}`, available targets are:\n\t{}",
+    available.join("\n\t")
+)]
+pub struct UnknownTargetError {
+    name: String,
+    available: Vec<String>,
+}
+
 impl WorkflowSpecification {
+    // an empty selection retains all targets
+    pub fn retain_targets(&mut self, names: &[String]) -> Result<(), UnknownTargetError> {
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(name) = names.iter().find(|name| !self.targets.contains_key(*name)) {
+            let mut available = self.targets.keys().cloned().collect::<Vec<_>>();
+            available.sort();
+            return Err(UnknownTargetError {
+                name: name.clone(),
+                available,
+            });
+        }
+
+        self.targets.retain(|name, _| names.contains(name));
+        Ok(())
+    }
+
     pub fn parse<S: AsRef<str>>(specification: S) -> miette::Result<WorkflowSpecification> {
         let specification = specification.as_ref();
 
@@ -155,9 +207,7 @@ impl WorkflowSpecification {
             .keep()
             .into_diagnostic()
             .context("failed to keep temporary input inspection file")?;
-        let input_inspection_path = PathBuf::from_path_buf(input_inspection_path).expect(&format!(
-            "expected the input inspection path to be valid utf8"
-        ));
+        let input_inspection_path = PathBuf::from_path_buf(input_inspection_path).expect("expected the input inspection path to be valid utf8");
         std::fs::write(&input_inspection_path, specification)
             .into_diagnostic()
             .context(format!(
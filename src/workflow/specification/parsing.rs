@@ -78,7 +78,7 @@ impl<T> WithSourceIndication<T> for serde_json::Result<T> {
                     .into_diagnostic()
                     .context("failed to keep temporary input inspection file")?;
                 let input_inspection_path = PathBuf::from_path_buf(input_inspection_path).expect(
-                    &format!("expected the input inspection path to be valid utf8"),
+                    "expected the input inspection path to be valid utf8",
                 );
 
                 let input = input.into();
@@ -0,0 +1,69 @@
+use camino::Utf8PathBuf as PathBuf;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+use crate::utils::IoError;
+
+use super::job::execution::SlurmJobID;
+
+const SUBMISSION_MANIFEST_PATH: &str = ".nixflow/submission.json";
+
+// the slurm jobs submitted by invocations which did not wait for them, so that a later
+// invocation can reattach to them; jobs of earlier submissions are kept for as long as they are
+// unfinished
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SubmissionManifest {
+    pub jobs: Vec<SubmittedJob>,
+}
+
+// several jobs can share the name of their step, so they are told apart by their outputs
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SubmittedJob {
+    pub step: String,
+    pub outputs: Vec<PathBuf>,
+    pub submission_time: SystemTime,
+    pub job_id: SlurmJobID,
+    pub dependencies: Vec<SlurmJobID>,
+    pub log: PathBuf,
+}
+
+#[derive(Clone, Debug, thiserror::Error)]
+pub enum SubmissionError {
+    #[error("failed to create the directory of the submission manifest `{0}`\n{1}")]
+    DirectoryCreation(PathBuf, IoError),
+
+    #[error("failed to read the submission manifest `{0}`\n{1}")]
+    Read(PathBuf, IoError),
+
+    #[error("failed to parse the submission manifest `{0}`\n{1}")]
+    Parsing(PathBuf, String),
+
+    #[error("failed to write the submission manifest `{0}`\n{1}")]
+    Write(PathBuf, IoError),
+}
+
+pub fn read_submission_manifest() -> Result<Option<SubmissionManifest>, SubmissionError> {
+    let path = PathBuf::from(SUBMISSION_MANIFEST_PATH);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(SubmissionError::Read(path, err.into())),
+    };
+
+    serde_json::from_str(&contents)
+        .map(Some)
+        .map_err(|err| SubmissionError::Parsing(path, err.to_string()))
+}
+
+pub fn write_submission_manifest(manifest: &SubmissionManifest) -> Result<(), SubmissionError> {
+    let path = PathBuf::from(SUBMISSION_MANIFEST_PATH);
+    let directory = path
+        .parent()
+        .expect("expected the manifest path to have a parent directory");
+    std::fs::create_dir_all(directory)
+        .map_err(|err| SubmissionError::DirectoryCreation(directory.to_owned(), err.into()))?;
+
+    let contents = serde_json::to_string_pretty(manifest)
+        .expect("expected serialization of the submission manifest to always succeed");
+    std::fs::write(&path, contents).map_err(|err| SubmissionError::Write(path, err.into()))
+}